@@ -0,0 +1,201 @@
+// Importing all useful modules
+use rustychip_8::chip8::audio::WavAudio;
+use rustychip_8::chip8::cpu::StepOutcome;
+use rustychip_8::chip8::debugger::{Debugger, Resume};
+use rustychip_8::chip8::dump::{self, DumpFormat};
+use rustychip_8::chip8::error::CpuError;
+use rustychip_8::chip8::frontend::{self, AudioSink, NullAudio};
+use rustychip_8::chip8::options::MachineOptions;
+use rustychip_8::chip8::savestate::CpuState;
+use rustychip_8::Machine;
+
+use std::{env, fs, io::Write};
+
+// Number of frames run when not specified (10 seconds)
+const DEFAULT_FRAMES: u32 = 600;
+
+// How long the rom runs
+enum Duration {
+    // A number of 60 Hz frames
+    Frames(u32),
+
+    // A number of instructions, timers ticking every instructions_per_frame instructions
+    Cycles(u32),
+}
+
+// Run the rom for the given duration, returns if the program exited.
+// The audio gets one frame of sound per timers tick, so it only depends on the emulated time
+fn run(
+    machine: &mut Machine,
+    duration: Duration,
+    audio: &mut dyn AudioSink,
+) -> Result<bool, CpuError> {
+    match duration {
+        Duration::Frames(frames) => {
+            for _ in 0..frames {
+                let outcome = machine.run_frame()?;
+                frontend::update_audio(machine, audio);
+                if outcome == StepOutcome::Exit {
+                    return Ok(true);
+                }
+            }
+        }
+        Duration::Cycles(cycles) => {
+            let instructions_per_frame = machine.instructions_per_frame().max(1);
+            for cycle in 1..=cycles {
+                if machine.step()? == StepOutcome::Exit {
+                    return Ok(true);
+                }
+                if cycle % instructions_per_frame == 0 {
+                    machine.tick_timers();
+                    frontend::update_audio(machine, audio);
+                }
+            }
+        }
+    }
+    Ok(false)
+}
+
+// Run the rom under the debugger, from its prompt, until the user quits or the program exits.
+// The duration doesn't apply, continue runs until a breakpoint (or for the frames it is given)
+fn run_debugged(
+    machine: &mut Machine,
+    debugger: &mut Debugger,
+    audio: &mut dyn AudioSink,
+) -> Result<bool, CpuError> {
+    while debugger.prompt(machine) == Resume::Continue {
+        loop {
+            match debugger.run_frame(machine) {
+                Ok(Some(outcome)) => {
+                    frontend::update_audio(machine, audio);
+                    if outcome == StepOutcome::Exit {
+                        return Ok(true);
+                    }
+                }
+                Ok(None) => {
+                    audio.pause();
+                    break;
+                }
+                // Errors are reported at the prompt, where the machine can be inspected
+                Err(error) => {
+                    println!("Emulation stopped: {}", error);
+                    audio.pause();
+                    break;
+                }
+            }
+        }
+    }
+    Ok(false)
+}
+
+// Runs a rom for a number of frames or cycles without any window, then dumps the screen
+fn main() {
+    // Parsing command line arguments
+    let mut options = MachineOptions::new();
+    let mut duration = Duration::Frames(DEFAULT_FRAMES);
+    let mut dump_file: Option<String> = None;
+    let mut load_state: Option<String> = None;
+    let mut save_state: Option<String> = None;
+    let mut debugger: Option<Debugger> = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--frames" => {
+                let frames = args
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .expect("Invalid number of frames after --frames!");
+                duration = Duration::Frames(frames);
+            }
+            "--cycles" => {
+                let cycles = args
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .expect("Invalid number of cycles after --cycles!");
+                duration = Duration::Cycles(cycles);
+            }
+            "--dump" => {
+                dump_file = Some(args.next().expect("No file specified after --dump!"));
+            }
+            "--load-state" => {
+                load_state = Some(args.next().expect("No file specified after --load-state!"));
+            }
+            "--debug" => debugger = Some(Debugger::new()),
+            "--save-state" => {
+                save_state = Some(args.next().expect("No file specified after --save-state!"));
+            }
+            _ => options
+                .parse(arg, &mut args)
+                .unwrap_or_else(|e| panic!("{}", e)),
+        }
+    }
+
+    // The instance of the machine, Octo sources are compiled first and keep their labels
+    let program = options.program().unwrap_or_else(|e| panic!("{}", e));
+    let mut machine = options
+        .build_program(&program)
+        .unwrap_or_else(|e| panic!("{}", e));
+    if let Some(debugger) = debugger.as_mut() {
+        debugger.set_labels(program.labels);
+    }
+
+    // Resuming from a save state
+    if let Some(path) = load_state {
+        let state = CpuState::load_file(&path).unwrap_or_else(|e| panic!("{}", e));
+        machine
+            .load_state(state)
+            .unwrap_or_else(|e| panic!("Can't load {}: {}", path, e));
+    }
+
+    // Recording the sound if asked, never playing it
+    let mut wav = options.wav_file.as_ref().map(|path| {
+        WavAudio::create(path, options.audio)
+            .unwrap_or_else(|e| panic!("Can't create {}: {}", path, e))
+    });
+
+    // Running the rom, it stops on the first error or when the program exits
+    let audio: &mut dyn AudioSink = match wav.as_mut() {
+        Some(wav) => wav,
+        None => &mut NullAudio,
+    };
+    let result = match debugger.as_mut() {
+        Some(debugger) => run_debugged(&mut machine, debugger, audio),
+        None => run(&mut machine, duration, audio),
+    };
+    if let Some(mut wav) = wav {
+        wav.finish()
+            .unwrap_or_else(|e| panic!("Can't write the WAV file: {}", e));
+    }
+
+    // Saving the final state
+    if let Some(path) = save_state {
+        machine
+            .state()
+            .save_file(&path)
+            .unwrap_or_else(|e| panic!("{}", e));
+    }
+
+    // Dumping the final screen, "-" prints it as ASCII art
+    if let Some(path) = dump_file {
+        let screen = machine.framebuffer();
+        if path == "-" {
+            print!("{}", dump::to_ascii(screen));
+            std::io::stdout()
+                .flush()
+                .expect("Can't write the screen dump");
+        } else {
+            let bytes = dump::dump(screen, DumpFormat::from_path(&path))
+                .unwrap_or_else(|e| panic!("Can't encode the screen dump: {}", e));
+            fs::write(&path, bytes).unwrap_or_else(|e| panic!("Can't write {}: {}", path, e));
+        }
+    }
+
+    match result {
+        Ok(true) => println!("Program exited"),
+        Ok(false) => {}
+        Err(error) => {
+            eprintln!("Emulation stopped: {}", error);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,103 @@
+// Importing all useful modules
+use rustychip_8::chip8::disasm::{self, Syntax};
+use rustychip_8::chip8::instruction::Variant;
+use rustychip_8::chip8::{asm, octo};
+
+use std::path::Path;
+use std::{env, fs};
+
+// Adress where roms are loaded
+const ROM_ADRESS: u16 = 0x200;
+
+// Print the disassembly of a rom: rustychip8-tools disasm <rom> [--syntax cowgod|octo]
+// [--variant chip8|schip|xochip] [--origin <hex adress>] [--source]
+fn disasm(mut args: impl Iterator<Item = String>) {
+    let mut rom_file: Option<String> = None;
+    let mut syntax = Syntax::Cowgod;
+    let mut variant = Variant::XoChip;
+    let mut origin = ROM_ADRESS;
+    let mut source = false;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--syntax" => {
+                syntax = args
+                    .next()
+                    .and_then(|name| Syntax::from_name(&name))
+                    .expect("Invalid syntax after --syntax!");
+            }
+            "--variant" => {
+                variant = args
+                    .next()
+                    .and_then(|name| Variant::from_name(&name))
+                    .expect("Invalid variant after --variant!");
+            }
+            "--origin" => {
+                origin = args
+                    .next()
+                    .and_then(|adress| {
+                        u16::from_str_radix(adress.trim_start_matches("0x"), 16).ok()
+                    })
+                    .expect("Invalid adress after --origin!");
+            }
+            "--source" => source = true,
+            _ if arg.starts_with("--") => panic!("Unknown option {}!", arg),
+            _ => rom_file = Some(arg),
+        }
+    }
+
+    let rom_file = rom_file.expect("No rom file specified!");
+    let rom = fs::read(&rom_file).unwrap_or_else(|e| panic!("Can't open {}: {}", rom_file, e));
+    for line in disasm::disassemble_rom(&rom, origin, syntax, variant) {
+        if source {
+            println!("{}", line.source(syntax));
+        } else {
+            println!("{}", line);
+        }
+    }
+}
+
+// Assemble a source file into a rom: rustychip8-tools asm <source> [-o <rom>], Octo sources (.8o)
+// are compiled. The rom is written next to the source with the .ch8 extension by default
+fn asm(mut args: impl Iterator<Item = String>) {
+    let mut source_file: Option<String> = None;
+    let mut rom_file: Option<String> = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output" => {
+                rom_file = Some(args.next().expect("No file specified after -o!"));
+            }
+            _ if arg.starts_with('-') => panic!("Unknown option {}!", arg),
+            _ => source_file = Some(arg),
+        }
+    }
+
+    let source_file = source_file.expect("No source file specified!");
+    let rom_file = rom_file.unwrap_or_else(|| {
+        Path::new(&source_file)
+            .with_extension("ch8")
+            .to_string_lossy()
+            .into_owned()
+    });
+    let rom = if source_file.ends_with(".8o") {
+        octo::compile_file(&source_file).map(|program| program.rom)
+    } else {
+        asm::assemble_file(&source_file)
+    };
+    let rom = rom.unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    fs::write(&rom_file, &rom).unwrap_or_else(|e| panic!("Can't write {}: {}", rom_file, e));
+    println!("{} bytes written to {}", rom.len(), rom_file);
+}
+
+// Tools working on chip8 programs, the first argument names the tool
+fn main() {
+    let mut args = env::args().skip(1);
+    match args.next().as_deref() {
+        Some("disasm") => disasm(args),
+        Some("asm") => asm(args),
+        Some(command) => panic!("Unknown command {}!", command),
+        None => panic!("No command specified, expected disasm or asm!"),
+    }
+}
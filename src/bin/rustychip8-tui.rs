@@ -0,0 +1,42 @@
+// Importing all useful modules
+use rustychip_8::chip8::frontend;
+use rustychip_8::chip8::options::MachineOptions;
+use rustychip_8::chip8::terminal::Terminal;
+
+use std::env;
+
+// Runs a rom in the terminal, for machines without any display
+fn main() {
+    // Parsing command line arguments
+    let mut options = MachineOptions::new();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        options
+            .parse(arg, &mut args)
+            .unwrap_or_else(|e| panic!("{}", e));
+    }
+
+    // The instance of the machine
+    let mut machine = options.build().unwrap_or_else(|e| panic!("{}", e));
+
+    // Where the buzzer sounds
+    let mut audio = options.audio_sink().unwrap_or_else(|e| panic!("{}", e));
+
+    // Running the emulation, the terminal is restored before reporting an error
+    let result = {
+        let mut terminal = Terminal::new(options.keymap.clone())
+            .unwrap_or_else(|e| panic!("Can't set up the terminal: {}", e));
+        frontend::run(
+            &mut machine,
+            &mut terminal,
+            audio.as_mut(),
+            &options.save_slots(),
+            &mut options.rewind_buffer(),
+            None,
+        )
+    };
+    if let Err(error) = result {
+        eprintln!("Emulation stopped: {}", error);
+        std::process::exit(1);
+    }
+}
@@ -1,2 +1,27 @@
+pub mod asm;
+pub mod audio;
 pub mod cpu;
-pub mod gpu;
\ No newline at end of file
+pub mod debugger;
+pub mod disasm;
+pub mod dump;
+pub mod error;
+pub mod font;
+pub mod framebuffer;
+pub mod frontend;
+#[cfg(feature = "gui")]
+pub mod gpu;
+pub mod instruction;
+pub mod keymap;
+pub mod machine;
+pub mod octo;
+pub mod options;
+pub mod quirks;
+pub mod rewind;
+pub mod rng;
+pub mod savestate;
+pub mod scheduler;
+#[cfg(feature = "audio")]
+pub mod speaker;
+#[cfg(feature = "tui")]
+pub mod terminal;
+pub mod timers;
@@ -0,0 +1,747 @@
+// Importing useful modules
+use super::instruction::Instruction;
+use std::collections::HashMap;
+use std::path::Path;
+use std::{error::Error, fmt, fs};
+
+// Adress where roms are loaded, the first byte assembled goes there
+pub const ORIGIN: u16 = 0x200;
+
+// Most nested include files, which also stops a file including itself
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+// Most nested constants, which also stops a constant defined in terms of itself
+const MAX_CONSTANT_DEPTH: usize = 16;
+
+// Every mnemonic, to tell unknown instructions from wrong operands
+const MNEMONICS: [&str; 29] = [
+    "CLS", "RET", "SYS", "JP", "CALL", "SE", "SNE", "LD", "ADD", "OR", "AND", "XOR", "SUB", "SHR",
+    "SUBN", "SHL", "RND", "DRW", "SKP", "SKNP", "SCD", "SCU", "SCR", "SCL", "EXIT", "LOW", "HIGH",
+    "PLANE", "AUDIO",
+];
+
+// Names of the operands which aren't values, they can't be used as labels or constants
+const RESERVED: [&str; 10] = ["I", "DT", "ST", "K", "F", "HF", "B", "R", "PITCH", "LONG"];
+
+// An error in a source file, pointing at where it happened
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    // The source file
+    pub file: String,
+
+    // Line and column, counted from 1. The line is 0 when the error isn't about a line
+    pub line: usize,
+    pub column: usize,
+
+    // What is wrong
+    pub message: String,
+
+    // The text of the line
+    pub source: String,
+}
+
+// Errors are shown as file:line:column: message, then the line with a caret under the column
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.line == 0 {
+            return write!(f, "{}: {}", self.file, self.message);
+        }
+        writeln!(
+            f,
+            "{}:{}:{}: {}",
+            self.file, self.line, self.column, self.message
+        )?;
+        writeln!(f, "    {}", self.source)?;
+        write!(f, "    {}^", " ".repeat(self.column.saturating_sub(1)))
+    }
+}
+
+impl Error for AsmError {}
+
+// A line of source, once the include files have been expanded
+struct SourceLine {
+    file: String,
+    number: usize,
+    text: String,
+}
+
+// SourceLine methods
+impl SourceLine {
+    // An error at a column of this line
+    fn error(&self, column: usize, message: String) -> AsmError {
+        AsmError {
+            file: self.file.clone(),
+            line: self.number,
+            column,
+            message,
+            source: self.text.clone(),
+        }
+    }
+}
+
+// The pieces of a line
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum TokenKind {
+    Word(String),
+    Number(i64),
+    Text(String),
+    Comma,
+    Colon,
+    Plus,
+    Minus,
+    Open,
+    Close,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Token {
+    kind: TokenKind,
+
+    // Counted from 1
+    column: usize,
+}
+
+// What an operand refers to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operand {
+    Register(u8),
+    Range(u8, u8),
+    I,
+    IndirectI,
+    Dt,
+    St,
+    K,
+    F,
+    Hf,
+    B,
+    R,
+    Pitch,
+    Long(i64),
+    Value(i64),
+}
+
+// A line holding bytes, kept between the passes
+struct Statement {
+    // Index of the line in the expanded source
+    line: usize,
+
+    // The mnemonic or directive, uppercase, and its column
+    name: String,
+    column: usize,
+
+    // The tokens of each operand
+    operands: Vec<Vec<Token>>,
+}
+
+// Labels and constants
+#[derive(Default)]
+struct Symbols {
+    // Adresses of the labels
+    labels: HashMap<String, u16>,
+
+    // Expressions of the constants, and the index of the line defining them
+    constants: HashMap<String, (Vec<Token>, usize)>,
+}
+
+// Assemble a source file into the bytes of a rom
+pub fn assemble_file(path: &str) -> Result<Vec<u8>, AsmError> {
+    let text = fs::read_to_string(path).map_err(|e| AsmError {
+        file: path.to_string(),
+        line: 0,
+        column: 0,
+        message: format!("Can't open: {}", e),
+        source: String::new(),
+    })?;
+    assemble(&text, path)
+}
+
+// Assemble source text into the bytes of a rom. The path names the source in the errors and
+// include files are looked up next to it
+pub fn assemble(text: &str, path: &str) -> Result<Vec<u8>, AsmError> {
+    let mut lines = Vec::new();
+    expand(text, path, 0, &mut lines)?;
+
+    // First pass: the adress of every label, and the statements left to encode
+    let mut symbols = Symbols::default();
+    let mut statements = Vec::new();
+    let mut adress = ORIGIN as usize;
+    for (index, line) in lines.iter().enumerate() {
+        let mut tokens = tokenize(&line.text).map_err(|(column, e)| line.error(column, e))?;
+
+        // A label starts the line
+        if let [Token {
+            kind: TokenKind::Word(name),
+            column,
+        }, Token {
+            kind: TokenKind::Colon,
+            ..
+        }, ..] = &tokens[..]
+        {
+            check_name(&symbols, line, name, *column)?;
+            symbols.labels.insert(name.clone(), adress as u16);
+            tokens.drain(..2);
+        }
+
+        let (name, column) = match tokens.first() {
+            Some(Token {
+                kind: TokenKind::Word(name),
+                column,
+            }) => (name.clone(), *column),
+            Some(token) => return Err(line.error(token.column, "Expected an instruction".into())),
+            None => continue,
+        };
+
+        // A constant: NAME EQU value
+        if let Some(Token {
+            kind: TokenKind::Word(equ),
+            ..
+        }) = tokens.get(1)
+        {
+            if equ.eq_ignore_ascii_case("EQU") {
+                check_name(&symbols, line, &name, column)?;
+                symbols
+                    .constants
+                    .insert(name, (tokens[2..].to_vec(), index));
+                continue;
+            }
+        }
+
+        let statement = Statement {
+            line: index,
+            name: name.to_uppercase(),
+            column,
+            operands: split_operands(&tokens[1..]),
+        };
+        adress += size(&statement);
+        if adress > 0x10000 {
+            return Err(line.error(column, "The program doesn't fit in memory".into()));
+        }
+        statements.push(statement);
+    }
+
+    // Second pass: encoding, now that all the symbols are known
+    let mut rom = Vec::new();
+    for statement in &statements {
+        let line = &lines[statement.line];
+        let evaluate = |tokens: &[Token]| evaluate(&symbols, &lines, line, tokens, 0);
+        match statement.name.as_str() {
+            "DB" => {
+                for operand in &statement.operands {
+                    match &operand[..] {
+                        [Token {
+                            kind: TokenKind::Text(text),
+                            ..
+                        }] => rom.extend(text.bytes()),
+                        _ => {
+                            let value = evaluate(operand)?;
+                            rom.push(fit(line, operand, value, 8)? as u8);
+                        }
+                    }
+                }
+            }
+            "DW" => {
+                for operand in &statement.operands {
+                    let value = evaluate(operand)?;
+                    rom.extend(fit(line, operand, value, 16)?.to_be_bytes());
+                }
+            }
+            _ => {
+                let mut operands = Vec::new();
+                for tokens in &statement.operands {
+                    operands.push(parse_operand(line, tokens, statement.column, &evaluate)?);
+                }
+                let instruction = encode(line, statement, &operands)?;
+                rom.extend(instruction.encode());
+            }
+        }
+    }
+    Ok(rom)
+}
+
+// Append the lines of a source to the expanded source, replacing the include directives by
+// the lines of the files they name
+fn expand(
+    text: &str,
+    path: &str,
+    depth: usize,
+    lines: &mut Vec<SourceLine>,
+) -> Result<(), AsmError> {
+    for (number, text) in text.lines().enumerate() {
+        let line = SourceLine {
+            file: path.to_string(),
+            number: number + 1,
+            text: text.trim_end().to_string(),
+        };
+
+        // INCLUDE "file", relative to the including file
+        let tokens = tokenize(&line.text).unwrap_or_default();
+        match &tokens[..] {
+            [Token {
+                kind: TokenKind::Word(word),
+                column,
+            }, rest @ ..]
+                if word.eq_ignore_ascii_case("INCLUDE") =>
+            {
+                let file = match rest {
+                    [Token {
+                        kind: TokenKind::Text(file),
+                        ..
+                    }] => file,
+                    _ => {
+                        return Err(line.error(*column, "Expected a file name after INCLUDE".into()))
+                    }
+                };
+                if depth >= MAX_INCLUDE_DEPTH {
+                    return Err(line.error(*column, "Too many nested include files".into()));
+                }
+                let included = Path::new(path).with_file_name(file);
+                let included = included.to_string_lossy();
+                let text = fs::read_to_string(included.as_ref())
+                    .map_err(|e| line.error(*column, format!("Can't open {}: {}", included, e)))?;
+                expand(&text, &included, depth + 1, lines)?;
+            }
+            _ => lines.push(line),
+        }
+    }
+    Ok(())
+}
+
+// Split a line into tokens, a semicolon starts a comment. Errors carry their column
+fn tokenize(text: &str) -> Result<Vec<Token>, (usize, String)> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let column = i + 1;
+        let start = i;
+        i += 1;
+        let kind = match c {
+            ';' => break,
+            c if c.is_whitespace() => continue,
+            ',' => TokenKind::Comma,
+            ':' => TokenKind::Colon,
+            '+' => TokenKind::Plus,
+            '-' => TokenKind::Minus,
+            '[' => TokenKind::Open,
+            ']' => TokenKind::Close,
+            '"' => {
+                while i < chars.len() && chars[i] != '"' {
+                    i += 1;
+                }
+                if i == chars.len() {
+                    return Err((column, "Unterminated string".into()));
+                }
+                i += 1;
+                TokenKind::Text(chars[start + 1..i - 1].iter().collect())
+            }
+            c if c.is_ascii_digit() || c == '#' || c == '$' || c == '%' => {
+                while i < chars.len() && chars[i].is_ascii_alphanumeric() {
+                    i += 1;
+                }
+                let text: String = chars[start..i].iter().collect();
+                TokenKind::Number(
+                    parse_number(&text).ok_or((column, format!("Invalid number {}", text)))?,
+                )
+            }
+            c if c.is_alphabetic() || c == '_' || c == '.' => {
+                while i < chars.len()
+                    && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '.')
+                {
+                    i += 1;
+                }
+                TokenKind::Word(chars[start..i].iter().collect())
+            }
+            c => return Err((column, format!("Unexpected character {}", c))),
+        };
+        tokens.push(Token { kind, column });
+    }
+    Ok(tokens)
+}
+
+// Numbers are decimal, hexadecimal (#FF, $FF or 0xFF) or binary (%1010 or 0b1010)
+fn parse_number(text: &str) -> Option<i64> {
+    let lower = text.to_lowercase();
+    let (digits, radix) = if let Some(digits) = lower.strip_prefix('#') {
+        (digits, 16)
+    } else if let Some(digits) = lower.strip_prefix('$') {
+        (digits, 16)
+    } else if let Some(digits) = lower.strip_prefix("0x") {
+        (digits, 16)
+    } else if let Some(digits) = lower.strip_prefix('%') {
+        (digits, 2)
+    } else if let Some(digits) = lower.strip_prefix("0b") {
+        (digits, 2)
+    } else {
+        (lower.as_str(), 10)
+    };
+    i64::from_str_radix(digits, radix).ok()
+}
+
+// A label or constant can be defined once, and not with the name of a register or operand
+fn check_name(
+    symbols: &Symbols,
+    line: &SourceLine,
+    name: &str,
+    column: usize,
+) -> Result<(), AsmError> {
+    if register(name).is_some() || RESERVED.contains(&name.to_uppercase().as_str()) {
+        return Err(line.error(column, format!("{} is a reserved name", name)));
+    }
+    if symbols.labels.contains_key(name) || symbols.constants.contains_key(name) {
+        return Err(line.error(column, format!("{} is already defined", name)));
+    }
+    Ok(())
+}
+
+// The operands of a statement, separated by commas
+fn split_operands(tokens: &[Token]) -> Vec<Vec<Token>> {
+    if tokens.is_empty() {
+        return Vec::new();
+    }
+    tokens
+        .split(|token| token.kind == TokenKind::Comma)
+        .map(|operand| operand.to_vec())
+        .collect()
+}
+
+// Number of bytes a statement assembles to, known before the symbols
+fn size(statement: &Statement) -> usize {
+    match statement.name.as_str() {
+        "DB" => statement
+            .operands
+            .iter()
+            .map(|operand| match &operand[..] {
+                [Token {
+                    kind: TokenKind::Text(text),
+                    ..
+                }] => text.len(),
+                _ => 1,
+            })
+            .sum(),
+        "DW" => 2 * statement.operands.len(),
+        _ => {
+            let long = statement
+                .operands
+                .get(1)
+                .and_then(|operand| operand.first());
+            match long.map(|token| &token.kind) {
+                Some(TokenKind::Word(word)) if word.eq_ignore_ascii_case("LONG") => 4,
+                _ => 2,
+            }
+        }
+    }
+}
+
+// Index of a register name, V0 to VF
+fn register(name: &str) -> Option<u8> {
+    let mut chars = name.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some('v' | 'V'), Some(digit), None) => digit.to_digit(16).map(|index| index as u8),
+        _ => None,
+    }
+}
+
+// Value of an expression: numbers, labels and constants added or subtracted
+fn evaluate(
+    symbols: &Symbols,
+    lines: &[SourceLine],
+    line: &SourceLine,
+    tokens: &[Token],
+    depth: usize,
+) -> Result<i64, AsmError> {
+    let mut value = 0;
+    let mut sign = Some(1);
+    for token in tokens {
+        let error = |message: String| line.error(token.column, message);
+        match (&token.kind, sign) {
+            (TokenKind::Plus, None) => sign = Some(1),
+            (TokenKind::Plus, Some(_)) => {}
+            (TokenKind::Minus, None) => sign = Some(-1),
+            (TokenKind::Minus, Some(s)) => sign = Some(-s),
+            (TokenKind::Number(number), Some(s)) => {
+                value += s * number;
+                sign = None;
+            }
+            (TokenKind::Word(name), Some(s)) => {
+                let symbol = if let Some(adress) = symbols.labels.get(name) {
+                    *adress as i64
+                } else if let Some((tokens, index)) = symbols.constants.get(name) {
+                    if depth >= MAX_CONSTANT_DEPTH {
+                        return Err(error(format!("{} is defined in terms of itself", name)));
+                    }
+                    let definition = &lines[*index];
+                    evaluate(symbols, lines, definition, tokens, depth + 1)?
+                } else {
+                    return Err(error(format!("Unknown label or constant {}", name)));
+                };
+                value += s * symbol;
+                sign = None;
+            }
+            _ => return Err(error("Unexpected token in expression".into())),
+        }
+    }
+    match (tokens.last(), sign) {
+        (None, _) => Err(line.error(line.text.len() + 1, "Missing value".into())),
+        (Some(token), Some(_)) => Err(line.error(token.column, "Missing value".into())),
+        _ => Ok(value),
+    }
+}
+
+// Check a value fits in a number of bits, bytes and words may also be negative
+fn fit(line: &SourceLine, tokens: &[Token], value: i64, bits: u32) -> Result<u16, AsmError> {
+    let max = (1 << bits) - 1;
+    let min = if bits == 8 || bits == 16 {
+        -(1 << (bits - 1))
+    } else {
+        0
+    };
+    if value < min || value > max {
+        let column = tokens.first().map_or(1, |token| token.column);
+        return Err(line.error(column, format!("{} doesn't fit in {} bits", value, bits)));
+    }
+    Ok((value & max) as u16)
+}
+
+// What an operand refers to, from its tokens
+fn parse_operand(
+    line: &SourceLine,
+    tokens: &[Token],
+    column: usize,
+    evaluate: &dyn Fn(&[Token]) -> Result<i64, AsmError>,
+) -> Result<Operand, AsmError> {
+    let word = |token: &Token| match &token.kind {
+        TokenKind::Word(word) => Some(word.to_uppercase()),
+        _ => None,
+    };
+    let operand = match tokens {
+        [] => return Err(line.error(column, "Missing operand".into())),
+        [Token {
+            kind: TokenKind::Open,
+            ..
+        }, i, Token {
+            kind: TokenKind::Close,
+            ..
+        }] if word(i).as_deref() == Some("I") => Operand::IndirectI,
+        [x, Token {
+            kind: TokenKind::Minus,
+            ..
+        }, y]
+            if word(x).and_then(|x| register(&x)).is_some()
+                && word(y).and_then(|y| register(&y)).is_some() =>
+        {
+            let x = word(x).and_then(|x| register(&x)).unwrap_or(0);
+            let y = word(y).and_then(|y| register(&y)).unwrap_or(0);
+            Operand::Range(x, y)
+        }
+        [first, rest @ ..] if word(first).as_deref() == Some("LONG") => {
+            Operand::Long(evaluate(rest)?)
+        }
+        [single] => match word(single).as_deref() {
+            Some(name) if register(name).is_some() => {
+                Operand::Register(register(name).unwrap_or(0))
+            }
+            Some("I") => Operand::I,
+            Some("DT") => Operand::Dt,
+            Some("ST") => Operand::St,
+            Some("K") => Operand::K,
+            Some("F") => Operand::F,
+            Some("HF") => Operand::Hf,
+            Some("B") => Operand::B,
+            Some("R") => Operand::R,
+            Some("PITCH") => Operand::Pitch,
+            _ => Operand::Value(evaluate(tokens)?),
+        },
+        _ => Operand::Value(evaluate(tokens)?),
+    };
+    Ok(operand)
+}
+
+// The instruction of a mnemonic and its operands
+fn encode(
+    line: &SourceLine,
+    statement: &Statement,
+    operands: &[Operand],
+) -> Result<Instruction, AsmError> {
+    use Operand as Op;
+
+    // A value operand checked against the size of its field
+    let value = |index: usize, bits: u32| -> Result<u16, AsmError> {
+        let tokens = &statement.operands[index];
+        match operands[index] {
+            Op::Value(value) | Op::Long(value) => fit(line, tokens, value, bits),
+            _ => Ok(0),
+        }
+    };
+    let adress = |index| value(index, 12);
+    let byte = |index| value(index, 8).map(|v| v as u8);
+    let nibble = |index| value(index, 4).map(|v| v as u8);
+
+    let instruction = match (statement.name.as_str(), operands) {
+        ("CLS", []) => Instruction::Cls,
+        ("RET", []) => Instruction::Ret,
+        ("SYS", [Op::Value(_)]) => Instruction::Sys(adress(0)?),
+        ("JP", [Op::Value(_)]) => Instruction::Jmp(adress(0)?),
+        ("JP", [Op::Register(0), Op::Value(_)]) => Instruction::JpV0(adress(1)?),
+        ("CALL", [Op::Value(_)]) => Instruction::Call(adress(0)?),
+        ("SE", [Op::Register(x), Op::Value(_)]) => Instruction::SeVx(*x, byte(1)?),
+        ("SE", [Op::Register(x), Op::Register(y)]) => Instruction::SeVxVy(*x, *y),
+        ("SNE", [Op::Register(x), Op::Value(_)]) => Instruction::SneVx(*x, byte(1)?),
+        ("SNE", [Op::Register(x), Op::Register(y)]) => Instruction::SneVxVy(*x, *y),
+        ("LD", [Op::Register(x), Op::Value(_)]) => Instruction::LdVx(*x, byte(1)?),
+        ("LD", [Op::Register(x), Op::Register(y)]) => Instruction::LdVxVy(*x, *y),
+        ("LD", [Op::I, Op::Value(_)]) => Instruction::LdI(adress(1)?),
+        ("LD", [Op::I, Op::Long(_)]) => Instruction::LdILong(value(1, 16)?),
+        ("LD", [Op::Register(x), Op::Dt]) => Instruction::LdVxDt(*x),
+        ("LD", [Op::Register(x), Op::K]) => Instruction::LdVxK(*x),
+        ("LD", [Op::Dt, Op::Register(x)]) => Instruction::LdDtVx(*x),
+        ("LD", [Op::St, Op::Register(x)]) => Instruction::LdStVx(*x),
+        ("LD", [Op::F, Op::Register(x)]) => Instruction::LdFVx(*x),
+        ("LD", [Op::Hf, Op::Register(x)]) => Instruction::LdHfVx(*x),
+        ("LD", [Op::B, Op::Register(x)]) => Instruction::LdBVx(*x),
+        ("LD", [Op::IndirectI, Op::Register(x)]) => Instruction::LdIVx(*x),
+        ("LD", [Op::Register(x), Op::IndirectI]) => Instruction::LdVxI(*x),
+        ("LD", [Op::R, Op::Register(x)]) => Instruction::LdRVx(*x),
+        ("LD", [Op::Register(x), Op::R]) => Instruction::LdVxR(*x),
+        ("LD", [Op::IndirectI, Op::Range(x, y)]) => Instruction::LdIVxVy(*x, *y),
+        ("LD", [Op::Range(x, y), Op::IndirectI]) => Instruction::LdVxVyI(*x, *y),
+        ("LD", [Op::Pitch, Op::Register(x)]) => Instruction::LdPitchVx(*x),
+        ("ADD", [Op::Register(x), Op::Value(_)]) => Instruction::AddVx(*x, byte(1)?),
+        ("ADD", [Op::Register(x), Op::Register(y)]) => Instruction::AddVxVy(*x, *y),
+        ("ADD", [Op::I, Op::Register(x)]) => Instruction::AddIVx(*x),
+        ("OR", [Op::Register(x), Op::Register(y)]) => Instruction::OrVxVy(*x, *y),
+        ("AND", [Op::Register(x), Op::Register(y)]) => Instruction::AndVxVy(*x, *y),
+        ("XOR", [Op::Register(x), Op::Register(y)]) => Instruction::XorVxVy(*x, *y),
+        ("SUB", [Op::Register(x), Op::Register(y)]) => Instruction::SubVxVy(*x, *y),
+        ("SUBN", [Op::Register(x), Op::Register(y)]) => Instruction::SubnVxVy(*x, *y),
+        // Without Vy, Vx is shifted in place whatever the quirks
+        ("SHR", [Op::Register(x)]) => Instruction::ShrVx(*x, *x),
+        ("SHR", [Op::Register(x), Op::Register(y)]) => Instruction::ShrVx(*x, *y),
+        ("SHL", [Op::Register(x)]) => Instruction::ShlVx(*x, *x),
+        ("SHL", [Op::Register(x), Op::Register(y)]) => Instruction::ShlVx(*x, *y),
+        ("RND", [Op::Register(x), Op::Value(_)]) => Instruction::RndVx(*x, byte(1)?),
+        ("DRW", [Op::Register(x), Op::Register(y), Op::Value(_)]) => {
+            Instruction::DrwVxVy(*x, *y, nibble(2)?)
+        }
+        ("SKP", [Op::Register(x)]) => Instruction::SkpVx(*x),
+        ("SKNP", [Op::Register(x)]) => Instruction::SknpVx(*x),
+        ("SCD", [Op::Value(_)]) => Instruction::ScrollDown(nibble(0)?),
+        ("SCU", [Op::Value(_)]) => Instruction::ScrollUp(nibble(0)?),
+        ("SCR", []) => Instruction::ScrollRight,
+        ("SCL", []) => Instruction::ScrollLeft,
+        ("EXIT", []) => Instruction::Exit,
+        ("LOW", []) => Instruction::Lores,
+        ("HIGH", []) => Instruction::Hires,
+        ("PLANE", [Op::Value(_)]) => Instruction::Plane(nibble(0)?),
+        ("AUDIO", []) => Instruction::LdAudioI,
+        (name, _) if MNEMONICS.contains(&name) => {
+            return Err(line.error(statement.column, format!("Invalid operands for {}", name)))
+        }
+        (name, _) => {
+            return Err(line.error(statement.column, format!("Unknown instruction {}", name)))
+        }
+    };
+    Ok(instruction)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::disasm::{self, Syntax};
+    use crate::chip8::instruction::Variant;
+    use std::env;
+
+    // Assemble the disassembly of a rom, written as source
+    fn reassemble(rom: &[u8], variant: Variant) -> Vec<u8> {
+        let source: Vec<String> = disasm::disassemble_rom(rom, ORIGIN, Syntax::Cowgod, variant)
+            .iter()
+            .map(|line| line.source(Syntax::Cowgod))
+            .collect();
+        assemble(&source.join("\n"), "disasm.s").unwrap()
+    }
+
+    #[test]
+    fn round_trip_through_the_disassembler() {
+        let source = "\
+LIMIT EQU 10
+start:
+    CLS
+    LD V0, LIMIT
+    LD I, sprite
+    LD I, LONG sprite
+loop:
+    DRW V0, V1, 5
+    ADD V1, -1
+    SE V1, LIMIT - 2
+    JP loop
+    CALL routine
+    LD [I], V0-V3
+    PLANE 3
+    EXIT
+routine:
+    SHR V2
+    RET
+sprite:
+    DB #F0, %10010000, \"HI\", 7
+    DW #1234, sprite";
+        let rom = assemble(source, "test.s").unwrap();
+        assert_eq!(reassemble(&rom, Variant::XoChip), rom);
+    }
+
+    #[test]
+    fn every_word_round_trips() {
+        for variant in [Variant::Chip8, Variant::Schip, Variant::XoChip] {
+            for high in 0..=0xFF_u8 {
+                let rom: Vec<u8> = (0..=0xFF_u8).flat_map(|low| [high, low]).collect();
+                assert_eq!(
+                    reassemble(&rom, variant),
+                    rom,
+                    "{:02X}XX, {:?}",
+                    high,
+                    variant
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn errors_point_at_the_line_and_column() {
+        let error = assemble("start:\n    LD V0, #100\n", "test.s").unwrap_err();
+        assert_eq!((error.line, error.column), (2, 12));
+        assert_eq!(
+            error.to_string(),
+            "test.s:2:12: 256 doesn't fit in 8 bits\n        LD V0, #100\n               ^"
+        );
+    }
+
+    #[test]
+    fn constants_defined_in_terms_of_themselves() {
+        let error =
+            assemble("FOO EQU BAR\nBAR EQU FOO + 1\n    LD V0, FOO\n", "test.s").unwrap_err();
+        // The error points at one of the definitions of the cycle
+        assert!(error.line == 1 || error.line == 2);
+        assert!(error.message.contains("defined in terms of itself"));
+    }
+
+    #[test]
+    fn include_files_are_relative_to_the_including_one() {
+        let dir = env::temp_dir().join(format!("rustychip8-asm-{}", std::process::id()));
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        fs::write(
+            dir.join("main.s"),
+            "    CALL draw\n    INCLUDE \"lib/draw.s\"\n",
+        )
+        .unwrap();
+        fs::write(dir.join("lib/draw.s"), "draw:\n    INCLUDE \"ret.s\"\n").unwrap();
+        fs::write(dir.join("lib/ret.s"), "    RET\n    JP nowhere\n").unwrap();
+
+        let error = assemble_file(&dir.join("main.s").to_string_lossy()).unwrap_err();
+        assert!(error.file.ends_with("ret.s"));
+        assert_eq!(error.line, 2);
+
+        fs::write(dir.join("lib/ret.s"), "    RET\n").unwrap();
+        let rom = assemble_file(&dir.join("main.s").to_string_lossy());
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(rom.unwrap(), [0x22, 0x02, 0x00, 0xEE]);
+    }
+}
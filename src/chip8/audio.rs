@@ -0,0 +1,247 @@
+// Importing useful modules
+use super::frontend::AudioSink;
+use super::scheduler::FRAME_RATE;
+use std::f32::consts::TAU;
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+
+// Sample rate of the WAV files (Hz), a whole number of samples per 60 Hz frame
+pub const WAV_SAMPLE_RATE: u32 = 44100;
+
+// Size of the header of a WAV file
+const WAV_HEADER_SIZE: u32 = 44;
+
+// Length of an XO-CHIP audio pattern in bits
+const PATTERN_BITS: f64 = 128.0;
+
+// Playback rate of the XO-CHIP audio patterns at the default pitch (bits per second)
+const PATTERN_BASE_RATE: f64 = 4000.0;
+
+// Shape of the buzzer tone
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Waveform {
+    Square,
+    Triangle,
+    Sawtooth,
+    Sine,
+}
+
+impl Waveform {
+    // Get a waveform by its name
+    pub fn from_name(name: &str) -> Option<Waveform> {
+        match name {
+            "square" => Some(Waveform::Square),
+            "triangle" => Some(Waveform::Triangle),
+            "sawtooth" | "saw" => Some(Waveform::Sawtooth),
+            "sine" => Some(Waveform::Sine),
+            _ => None,
+        }
+    }
+
+    // Value of the wave (-1 to 1) at a phase (0 to 1)
+    fn value(&self, phase: f32) -> f32 {
+        match self {
+            Waveform::Square => {
+                if phase < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            Waveform::Triangle => 4.0 * (phase - 0.5).abs() - 1.0,
+            Waveform::Sawtooth => 2.0 * phase - 1.0,
+            Waveform::Sine => (TAU * phase).sin(),
+        }
+    }
+}
+
+// How the buzzer sounds
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AudioSettings {
+    // Frequency of the tone (Hz)
+    pub frequency: f32,
+
+    // Shape of the tone
+    pub waveform: Waveform,
+
+    // Volume, from 0 (silent) to 1
+    pub volume: f32,
+
+    // If nothing should be heard at all
+    pub muted: bool,
+}
+
+impl Default for AudioSettings {
+    fn default() -> Self {
+        AudioSettings {
+            frequency: 440.0,
+            waveform: Waveform::Square,
+            volume: 0.25,
+            muted: false,
+        }
+    }
+}
+
+// The tone generator of the buzzer, shared by all the audio sinks
+pub struct Tone {
+    settings: AudioSettings,
+
+    // Position in the current period of the wave (0 to 1)
+    phase: f32,
+
+    // If the buzzer sounds
+    active: bool,
+
+    // XO-CHIP pattern played instead of the wave, and its rate (bits per second)
+    pattern: Option<[u8; 16]>,
+    pattern_rate: f64,
+
+    // Position in the pattern (0 to 128 bits)
+    pattern_position: f64,
+}
+
+// Tone methods
+impl Tone {
+    pub fn new(settings: AudioSettings) -> Tone {
+        Tone {
+            settings,
+            phase: 0.0,
+            active: false,
+            pattern: None,
+            pattern_rate: PATTERN_BASE_RATE,
+            pattern_position: 0.0,
+        }
+    }
+
+    // Play an XO-CHIP pattern instead of the wave, its rate is 4000 bits per second at pitch 64
+    // and doubles every 48 pitch steps
+    pub fn set_pattern(&mut self, pattern: Option<&[u8; 16]>, pitch: u8) {
+        self.pattern = pattern.copied();
+        self.pattern_rate = PATTERN_BASE_RATE * 2f64.powf((pitch as f64 - 64.0) / 48.0);
+    }
+
+    // Start or stop the buzzer
+    pub fn set_active(&mut self, active: bool) {
+        self.active = active;
+    }
+
+    // Next sample (-volume to volume) at a sample rate, silence while the buzzer is off
+    pub fn sample(&mut self, sample_rate: u32) -> f32 {
+        if !self.active || self.settings.muted {
+            self.phase = 0.0;
+            self.pattern_position = 0.0;
+            return 0.0;
+        }
+
+        // Each bit of the pattern is a high or low level
+        if let Some(pattern) = &self.pattern {
+            let bit = self.pattern_position as usize;
+            let high = pattern[bit / 8] & (0x80 >> (bit % 8)) != 0;
+            self.pattern_position =
+                (self.pattern_position + self.pattern_rate / sample_rate as f64) % PATTERN_BITS;
+            return if high {
+                self.settings.volume
+            } else {
+                -self.settings.volume
+            };
+        }
+
+        let value = self.settings.waveform.value(self.phase) * self.settings.volume;
+        self.phase = (self.phase + self.settings.frequency / sample_rate as f32).fract();
+        value
+    }
+}
+
+// Audio sink recording the buzzer to a 16 bits mono WAV file, one frame of samples per call.
+// The samples only depend on the emulated frames, so a run always records the same file.
+// The file is complete once finished, or dropped
+pub struct WavAudio {
+    writer: Option<BufWriter<File>>,
+    tone: Tone,
+
+    // Number of samples written
+    samples: u32,
+}
+
+// WavAudio methods
+impl WavAudio {
+    // Create the file, the header gets its sizes when finished
+    pub fn create(path: &str, settings: AudioSettings) -> io::Result<WavAudio> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        write_wav_header(&mut writer, WAV_SAMPLE_RATE, 0)?;
+        Ok(WavAudio {
+            writer: Some(writer),
+            tone: Tone::new(settings),
+            samples: 0,
+        })
+    }
+
+    // Complete the header and close the file
+    pub fn finish(&mut self) -> io::Result<()> {
+        if let Some(mut writer) = self.writer.take() {
+            writer.seek(SeekFrom::Start(0))?;
+            write_wav_header(&mut writer, WAV_SAMPLE_RATE, self.samples)?;
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    // Write the samples of one frame
+    fn write_frame(&mut self) -> io::Result<()> {
+        if let Some(writer) = self.writer.as_mut() {
+            for _ in 0..WAV_SAMPLE_RATE / FRAME_RATE {
+                let sample = (self.tone.sample(WAV_SAMPLE_RATE) * i16::MAX as f32) as i16;
+                writer.write_all(&sample.to_le_bytes())?;
+                self.samples += 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl AudioSink for WavAudio {
+    fn set_buzzer(&mut self, active: bool) {
+        self.tone.set_active(active);
+        if let Err(e) = self.write_frame() {
+            eprintln!("Can't write the WAV file: {}", e);
+            self.writer = None;
+        }
+    }
+
+    fn set_pattern(&mut self, pattern: Option<&[u8; 16]>, pitch: u8) {
+        self.tone.set_pattern(pattern, pitch);
+    }
+
+    fn pause(&mut self) {
+        self.tone.set_active(false);
+    }
+}
+
+impl Drop for WavAudio {
+    fn drop(&mut self) {
+        if let Err(e) = self.finish() {
+            eprintln!("Can't write the WAV file: {}", e);
+        }
+    }
+}
+
+// Header of a 16 bits mono PCM WAV file holding a number of samples
+pub fn write_wav_header(writer: &mut dyn Write, sample_rate: u32, samples: u32) -> io::Result<()> {
+    let data_size = samples * 2;
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&(WAV_HEADER_SIZE - 8 + data_size).to_le_bytes())?;
+    writer.write_all(b"WAVE")?;
+
+    // Format: PCM, 1 channel, 2 bytes per sample
+    writer.write_all(b"fmt ")?;
+    writer.write_all(&16u32.to_le_bytes())?;
+    writer.write_all(&1u16.to_le_bytes())?;
+    writer.write_all(&1u16.to_le_bytes())?;
+    writer.write_all(&sample_rate.to_le_bytes())?;
+    writer.write_all(&(sample_rate * 2).to_le_bytes())?;
+    writer.write_all(&2u16.to_le_bytes())?;
+    writer.write_all(&16u16.to_le_bytes())?;
+
+    writer.write_all(b"data")?;
+    writer.write_all(&data_size.to_le_bytes())
+}
@@ -1,20 +1,49 @@
 // Importing useful modules
-use rand::Rng;
-use std::{fs, io::Read, vec};
+use super::error::CpuError;
+use super::font::{Font, BIG_FONT_ADDRESS, BIG_FONT_SET, BIG_GLYPH_SIZE, FONT_ADDRESS, GLYPH_SIZE};
+use super::framebuffer::{
+    Framebuffer, HIRES_HEIGHT, HIRES_WIDTH, LORES_HEIGHT, LORES_WIDTH, PLANES,
+};
+use super::quirks::{MemoryIncrement, Quirks};
+use super::rng::Rng;
+use super::savestate::{CpuState, SaveStateError};
+use super::timers::Timers;
+use std::{io, vec};
+
+// Result of a CPU step
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepOutcome {
+    // An instruction has been executed
+    Executed,
+
+    // Execution is halted until a key is pressed then released (FX0A)
+    WaitingForKey,
+
+    // Execution is halted until the next 60 Hz frame after a draw (display wait quirk)
+    WaitingForVblank,
+
+    // The program has exited (00FD)
+    Exit,
+}
+
+// A FX0A instruction waiting for a key to be pressed then released
+struct KeyWait {
+    // Register receiving the key number
+    register: u8,
 
-// Colors
-const BLACK: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
-const WHITE: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+    // Key pressed since the wait began, completed on its release
+    pressed: Option<u8>,
+}
 
 // The CPU of the Chip8
 pub struct Cpu {
     // The Program Counter 5PC)
     pc: u16,
 
-    // The Stack Pointer (SP)
-    sp: u8,
+    // The Stack Pointer (SP), number of return adresses on the stack
+    sp: usize,
 
-    // The stack
+    // The stack, its size is the maximum depth of nested subroutines calls
     stack: Vec<u16>,
 
     // The chip8 memory
@@ -29,102 +58,249 @@ pub struct Cpu {
     // The current opcode where the PC is
     curr_opcode: u16,
 
-    //     // The screen buffer
-    screen_buffer: Vec<Vec<[f32; 4]>>,
+    // The adress of the current opcode
+    curr_pc: u16,
+
+    // The screen, 64x32 or 128x64 in high resolution
+    screen_buffer: Framebuffer,
+
+    // Planes affected by drawing, clearing and scrolling (XO-CHIP)
+    planes: u8,
 
     // Timers
-    delta_timer: u16,
-    sub_timer: u16,
+    timers: Timers,
 
     // Keys
     keys: [bool; 16],
+
+    // Set while execution is halted by FX0A
+    key_wait: Option<KeyWait>,
+
+    // Set while execution is halted until the next frame after a draw
+    vblank_wait: bool,
+
+    // Set once the program has exited
+    exited: bool,
+
+    // SUPER-CHIP RPL user flags (FX75/FX85)
+    rpl_flags: [u8; 16],
+
+    // XO-CHIP audio pattern buffer (F002), the plain buzzer sounds until a pattern is loaded, and pitch (FX3A)
+    audio_pattern: Option<[u8; 16]>,
+    pitch: u8,
+
+    // Random numbers generator (CXNN)
+    rng: Rng,
+
+    // Platform specific behaviours
+    quirks: Quirks,
 }
 
 // All CPU methods
 impl Cpu {
-    // Constructor
-    pub fn new(rom_file: Option<String>) -> Cpu {
-        let mut rom: std::fs::File;
-        let mut buffer: Vec<u8> = Vec::new();
-
-        // If rom file arg is not empty
-        if rom_file.is_some() {
-            // Read rom file
-            let value = rom_file.unwrap();
-            rom = fs::File::open(&value).expect(&format!("Can't open rom file {}!", value));
-            rom.read_to_end(&mut buffer).expect("Can't read rom file");
-        }
-
-        // Reading rom file byte per byte to vector
-        let mut _ram: Vec<u8> = vec![0; 4096];
-        let mut i = 0x200;
-        for value in buffer {
-            _ram[i] = value;
-            i = i + 1;
+    // Constructor, the rom is loaded at 0x200
+    pub fn new(rom: &[u8], font: &Font, quirks: Quirks) -> io::Result<Cpu> {
+        // Copying the rom to memory
+        let mut _ram: Vec<u8> = vec![0; quirks.memory_size];
+        if 0x200 + rom.len() > _ram.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Rom is too big for {} bytes of memory", _ram.len()),
+            ));
         }
+        _ram[0x200..0x200 + rom.len()].copy_from_slice(rom);
 
         // Loading fonts to memory
-        pub const CHIP8_FONT_SET: [u8; 80] = [
-            0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
-            0x20, 0x60, 0x20, 0x20, 0x70, // 1
-            0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
-            0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
-            0x90, 0x90, 0xF0, 0x10, 0x10, // 4
-            0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
-            0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
-            0xF0, 0x10, 0x20, 0x40, 0x40, // 7
-            0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
-            0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
-            0xF0, 0x90, 0xF0, 0x90, 0x90, // A
-            0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
-            0xF0, 0x80, 0x80, 0x80, 0xF0, // C
-            0xE0, 0x90, 0x90, 0x90, 0xE0, // D
-            0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
-            0xF0, 0x80, 0xF0, 0x80, 0x80, // F
-        ];
-
-        i = 0;
-        for value in CHIP8_FONT_SET {
-            _ram[i] = value;
-            i = i + 1;
-        }
+        let start = FONT_ADDRESS as usize;
+        _ram[start..start + 80].copy_from_slice(font.glyphs());
+        let start = BIG_FONT_ADDRESS as usize;
+        _ram[start..start + BIG_FONT_SET.len()].copy_from_slice(&BIG_FONT_SET);
 
         // Creating new instance of a CPU from all these parameters
-        let _cpu = Cpu {
+        Ok(Cpu {
             pc: 0x200,
             sp: 0,
-            stack: vec![0],
+            stack: vec![0; quirks.stack_depth],
             ram: _ram,
             registers: vec![0; 16],
             i_register: 0,
             curr_opcode: 0,
-            screen_buffer: vec![vec![[0.1, 0.1, 0.1, 1.0]; 32]; 68],
-            delta_timer: 0,
-            sub_timer: 0,
+            curr_pc: 0x200,
+            screen_buffer: Framebuffer::lores(),
+            planes: 1,
+            timers: Timers::new(),
             keys: [false; 16],
-        };
+            key_wait: None,
+            vblank_wait: false,
+            exited: false,
+            rpl_flags: [0; 16],
+            audio_pattern: None,
+            pitch: 64,
+            rng: Rng::from_entropy(),
+            quirks,
+        })
+    }
+
+    // Snapshot of the whole CPU
+    pub fn state(&self) -> CpuState {
+        CpuState {
+            pc: self.pc,
+            sp: self.sp,
+            stack: self.stack.clone(),
+            i_register: self.i_register,
+            registers: self.registers.clone(),
+            ram: self.ram.clone(),
+            curr_opcode: self.curr_opcode,
+            curr_pc: self.curr_pc,
+            delay_timer: self.timers.delay,
+            sound_timer: self.timers.sound,
+            keys: self.keys,
+            key_wait: self.key_wait.as_ref().map(|w| (w.register, w.pressed)),
+            vblank_wait: self.vblank_wait,
+            exited: self.exited,
+            screen: self.screen_buffer.clone(),
+            planes: self.planes,
+            rpl_flags: self.rpl_flags,
+            audio_pattern: self.audio_pattern,
+            pitch: self.pitch,
+            rng_state: self.rng.state(),
+            quirks: self.quirks,
+        }
+    }
 
-        return _cpu;
+    // Restore a CPU from a snapshot, checking it is consistent with its quirks
+    pub fn from_state(state: CpuState) -> Result<Cpu, SaveStateError> {
+        let quirks = state.quirks;
+        if state.ram.len() != quirks.memory_size || state.ram.len() < 0x200 {
+            return Err(SaveStateError::Invalid("memory size"));
+        }
+        if state.stack.len() != quirks.stack_depth || state.sp > state.stack.len() {
+            return Err(SaveStateError::Invalid("stack"));
+        }
+        if state.registers.len() != 16 {
+            return Err(SaveStateError::Invalid("registers"));
+        }
+        let size = (state.screen.width(), state.screen.height());
+        let rows_complete = (0..PLANES).all(|plane| state.screen.rows(plane).len() == size.1);
+        if !rows_complete
+            || (size != (LORES_WIDTH, LORES_HEIGHT) && size != (HIRES_WIDTH, HIRES_HEIGHT))
+        {
+            return Err(SaveStateError::Invalid("screen size"));
+        }
+        if matches!(state.key_wait, Some((register, pressed)) if register > 0xF || pressed.is_some_and(|k| k > 0xF))
+        {
+            return Err(SaveStateError::Invalid("key wait"));
+        }
+
+        Ok(Cpu {
+            pc: state.pc,
+            sp: state.sp,
+            stack: state.stack,
+            ram: state.ram,
+            registers: state.registers,
+            i_register: state.i_register,
+            curr_opcode: state.curr_opcode,
+            curr_pc: state.curr_pc,
+            screen_buffer: state.screen,
+            planes: state.planes & 0b11,
+            timers: Timers {
+                delay: state.delay_timer,
+                sound: state.sound_timer,
+            },
+            keys: state.keys,
+            key_wait: state
+                .key_wait
+                .map(|(register, pressed)| KeyWait { register, pressed }),
+            vblank_wait: state.vblank_wait,
+            exited: state.exited,
+            rpl_flags: state.rpl_flags,
+            audio_pattern: state.audio_pattern,
+            pitch: state.pitch,
+            rng: Rng::new(state.rng_state),
+            quirks,
+        })
     }
 
     // A CPU step
-    pub fn run(&mut self) {
+    pub fn run(&mut self) -> Result<StepOutcome, CpuError> {
+        // Nothing runs anymore once the program has exited
+        if self.exited {
+            return Ok(StepOutcome::Exit);
+        }
+
+        // Execution is halted until FX0A gets its key
+        if self.key_wait.is_some() {
+            return Ok(StepOutcome::WaitingForKey);
+        }
+
+        // Execution is halted until the next frame after a draw
+        if self.vblank_wait {
+            return Ok(StepOutcome::WaitingForVblank);
+        }
+
         // Fetch
         let pc = self.pc;
-        self.fetch(pc);
+        self.fetch(pc)?;
 
         // Decode and Execute
-        self.decode_and_execute();
+        self.decode_and_execute()?;
+        if self.exited {
+            return Ok(StepOutcome::Exit);
+        }
+        Ok(StepOutcome::Executed)
+    }
+
+    // Update the state of a key of the keypad (0x0-0xF) from the frontend input
+    pub fn set_key(&mut self, key: u8, pressed: bool) {
+        let key = key & 0x0F;
+        self.keys[key as usize] = pressed;
+
+        // FX0A completes once a key has been pressed then released (VIP behaviour)
+        if let Some(wait) = &mut self.key_wait {
+            match wait.pressed {
+                None if pressed => wait.pressed = Some(key),
+                Some(waited) if waited == key && !pressed => {
+                    self.registers[wait.register as usize] = key;
+                    self.key_wait = None;
+                }
+                _ => {}
+            }
+        }
     }
 
-    // Fetch current opcode pointed by the PC
-    pub fn decode_and_execute(&mut self) {
+    // Restart the random numbers generator from a seed, to reproduce a run
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = Rng::new(seed);
+    }
+
+    // Decrement the delay and sound timers once, frontends call this once per 60 Hz frame
+    pub fn tick_timers(&mut self) {
+        self.timers.tick();
+        self.vblank_wait = false;
+    }
+
+    // Decode and execute the current opcode
+    pub fn decode_and_execute(&mut self) -> Result<(), CpuError> {
         match self.curr_opcode & 0xF000 {
             // For the 0x0FFF opcodes
             0x0000 => match self.curr_opcode & 0x0FFF {
                 0x00E0 => self.cls(),
 
-                0x00EE => self.ret(),
+                0x00EE => self.ret()?,
+
+                0x00FB => self.scroll(4, 0),
+
+                0x00FC => self.scroll(-4, 0),
+
+                0x00FD => self.exited = true,
+
+                0x00FE => self.set_hires(false),
+
+                0x00FF => self.set_hires(true),
+
+                op if op & 0x0FF0 == 0x00C0 => self.scroll(0, (op & 0x000F) as isize),
+
+                op if op & 0x0FF0 == 0x00D0 => self.scroll(0, -((op & 0x000F) as isize)),
 
                 _ => {
                     self.pc = self.curr_opcode;
@@ -135,7 +311,7 @@ impl Cpu {
             0x1000 => self.jmp(self.curr_opcode & 0x0FFF),
 
             // For the 0x2FFF opcode
-            0x2000 => self.call(self.curr_opcode & 0x0FFF),
+            0x2000 => self.call(self.curr_opcode & 0x0FFF)?,
 
             // For the 0x3FFF opcodes
             0x3000 => self.se_vx(
@@ -149,11 +325,22 @@ impl Cpu {
                 (self.curr_opcode & 0x00FF) as u8,
             ),
 
-            // For the 0x4FFF opcode
-            0x5000 => self.se_vx_vy(
-                ((self.curr_opcode & 0x0F00) >> 8) as u8,
-                ((self.curr_opcode & 0x00F0) >> 4) as u8,
-            ),
+            // For the 0x5FFF opcode
+            0x5000 => match self.curr_opcode & 0x000F {
+                0x0000 => self.se_vx_vy(
+                    ((self.curr_opcode & 0x0F00) >> 8) as u8,
+                    ((self.curr_opcode & 0x00F0) >> 4) as u8,
+                ),
+                0x0002 => self.ld_i_vx_vy(
+                    ((self.curr_opcode & 0x0F00) >> 8) as u8,
+                    ((self.curr_opcode & 0x00F0) >> 4) as u8,
+                )?,
+                0x0003 => self.ld_vx_vy_i(
+                    ((self.curr_opcode & 0x0F00) >> 8) as u8,
+                    ((self.curr_opcode & 0x00F0) >> 4) as u8,
+                )?,
+                _ => return Err(self.unknown_opcode()),
+            },
 
             // For the 0x6FFF opcodes
             0x6000 => self.ld_vx(
@@ -193,23 +380,29 @@ impl Cpu {
                     ((self.curr_opcode & 0x0F00) >> 8) as u8,
                     ((self.curr_opcode & 0x00F0) >> 4) as u8,
                 ),
-                0x0006 => self.shr_vx(((self.curr_opcode & 0x0F00) >> 8) as u8),
+                0x0006 => self.shr_vx(
+                    ((self.curr_opcode & 0x0F00) >> 8) as u8,
+                    ((self.curr_opcode & 0x00F0) >> 4) as u8,
+                ),
                 0x0007 => self.subn_vx_vy(
                     ((self.curr_opcode & 0x0F00) >> 8) as u8,
                     ((self.curr_opcode & 0x00F0) >> 4) as u8,
                 ),
-                0x000E => self.shl_vx(((self.curr_opcode & 0x0F00) >> 8) as u8),
-                _ => {
-                    println!("Not implemented opcode: {:#04x}", self.curr_opcode);
-                    std::process::exit(1);
-                }
+                0x000E => self.shl_vx(
+                    ((self.curr_opcode & 0x0F00) >> 8) as u8,
+                    ((self.curr_opcode & 0x00F0) >> 4) as u8,
+                ),
+                _ => return Err(self.unknown_opcode()),
             },
 
-            // For the 0x9FFF opcode
-            0x9000 => self.sne_vx_vy(
-                ((self.curr_opcode & 0x0F00) >> 8) as u8,
-                ((self.curr_opcode & 0x00F0) >> 4) as u8,
-            ),
+            // For the 0x9FF0 opcode
+            0x9000 => match self.curr_opcode & 0x000F {
+                0x0000 => self.sne_vx_vy(
+                    ((self.curr_opcode & 0x0F00) >> 8) as u8,
+                    ((self.curr_opcode & 0x00F0) >> 4) as u8,
+                ),
+                _ => return Err(self.unknown_opcode()),
+            },
 
             // For the 0xAFFF opcodes
             0xA000 => self.ld_i(self.curr_opcode & 0x0FFF),
@@ -228,82 +421,157 @@ impl Cpu {
                 ((self.curr_opcode & 0x0F00) >> 8) as u8,
                 ((self.curr_opcode & 0x00F0) >> 4) as u8,
                 (self.curr_opcode & 0x000F) as u8,
-            ),
+            )?,
 
             // For the 0xEFFF opcodes
-            0xE000 => match self.curr_opcode & 0x00F0 {
-                0x0090 => self.skp_vx(((self.curr_opcode & 0x0F00) >> 8) as u8),
-                0x00A0 => self.sknp_vx(((self.curr_opcode & 0x0F00) >> 8) as u8),
-                _ => {
-                    println!("Not implemented opcode: {:#04x}", self.curr_opcode);
-                    std::process::exit(1);
-                }
+            0xE000 => match self.curr_opcode & 0x00FF {
+                0x009E => self.skp_vx(((self.curr_opcode & 0x0F00) >> 8) as u8),
+                0x00A1 => self.sknp_vx(((self.curr_opcode & 0x0F00) >> 8) as u8),
+                _ => return Err(self.unknown_opcode()),
             },
 
             // For the 0xFFFF opcodes
-            0xF000 => match self.curr_opcode & 0x00F0 {
-                0x0000 => match self.curr_opcode & 0x000F {
-                    0x0007 => self.ld_vx_dt(((self.curr_opcode & 0x0F00) >> 8) as u8),
-                    0x000A => self.ld_vx_k(((self.curr_opcode & 0x0F00) >> 8) as u8),
-                    _ => {
-                        println!("Not implemented opcode: {:#04x}", self.curr_opcode);
-                        std::process::exit(1);
-                    }
-                },
-                0x0010 => match self.curr_opcode & 0x000F {
-                    0x0005 => self.ld_dt_vx(((self.curr_opcode & 0x0F00) >> 8) as u8),
-                    0x0008 => self.ld_st_vx(((self.curr_opcode & 0x0F00) >> 8) as u8),
-                    0x000E => self.add_i_vx(((self.curr_opcode & 0x0F00) >> 8) as u8),
-                    _ => {
-                        println!("Not implemented opcode: {:#04x}", self.curr_opcode);
-                        std::process::exit(1);
-                    }
-                },
-
-                0x0020 => self.ld_f_vx(),
-                0x0030 => self.ld_b_vx(((self.curr_opcode & 0x0F00) >> 8) as u8),
-                0x0050 => self.ld_i_vx(((self.curr_opcode & 0x0F00) >> 8) as u8),
-                0x0060 => self.ld_vx_i(((self.curr_opcode & 0x0F00) >> 8) as u8),
-
-                _ => {
-                    println!("Not implemented opcode: {:#04x}", self.curr_opcode);
-                    std::process::exit(1);
+            0xF000 => {
+                let x = ((self.curr_opcode & 0x0F00) >> 8) as u8;
+                match self.curr_opcode & 0x00FF {
+                    0x0000 if x == 0 => self.ld_i_long()?,
+                    0x0001 => self.plane(x),
+                    0x0002 if x == 0 => self.ld_audio_i()?,
+                    0x0007 => self.ld_vx_dt(x),
+                    0x000A => self.ld_vx_k(x),
+                    0x0015 => self.ld_dt_vx(x),
+                    0x0018 => self.ld_st_vx(x),
+                    0x001E => self.add_i_vx(x),
+                    0x0029 => self.ld_f_vx(x),
+                    0x0030 => self.ld_hf_vx(x),
+                    0x0033 => self.ld_b_vx(x)?,
+                    0x003A => self.ld_pitch_vx(x),
+                    0x0055 => self.ld_i_vx(x)?,
+                    0x0065 => self.ld_vx_i(x)?,
+                    0x0075 => self.ld_r_vx(x),
+                    0x0085 => self.ld_vx_r(x),
+                    _ => return Err(self.unknown_opcode()),
                 }
-            },
-
-            _ => {
-                println!("Not implemented opcode: {:#04x}", self.curr_opcode);
-                std::process::exit(1);
             }
+
+            _ => return Err(self.unknown_opcode()),
         }
+
+        Ok(())
     }
 
     // Fetch byte pointed by the PC
-    pub fn fetch(&mut self, adress: u16) {
-        self.curr_opcode = ((((self.ram[(adress) as usize]) as u16) << 8) as u16)
-            | (self.ram[(adress + 1) as usize] as u16);
-        self.pc = self.pc + 2;
+    pub fn fetch(&mut self, adress: u16) -> Result<(), CpuError> {
+        if adress as usize + 1 >= self.ram.len() {
+            return Err(CpuError::PcOutOfBounds {
+                pc: adress,
+                opcode: self.curr_opcode,
+            });
+        }
+
+        self.curr_pc = adress;
+        self.curr_opcode =
+            ((self.ram[adress as usize] as u16) << 8) | (self.ram[(adress + 1) as usize] as u16);
+        self.pc = self.pc.wrapping_add(2);
+        Ok(())
     }
 
-    // Clearing screen
+    // Error for an opcode not part of the instruction set
+    fn unknown_opcode(&self) -> CpuError {
+        CpuError::UnknownOpcode {
+            pc: self.curr_pc,
+            opcode: self.curr_opcode,
+        }
+    }
+
+    // Reading a byte from memory
+    fn read(&self, adress: usize) -> Result<u8, CpuError> {
+        self.ram
+            .get(adress)
+            .copied()
+            .ok_or(CpuError::MemoryOutOfBounds {
+                pc: self.curr_pc,
+                opcode: self.curr_opcode,
+                adress,
+            })
+    }
+
+    // Writing a byte to memory
+    fn write(&mut self, adress: usize, val: u8) -> Result<(), CpuError> {
+        match self.ram.get_mut(adress) {
+            Some(byte) => {
+                *byte = val;
+                Ok(())
+            }
+            None => Err(CpuError::MemoryOutOfBounds {
+                pc: self.curr_pc,
+                opcode: self.curr_opcode,
+                adress,
+            }),
+        }
+    }
+
+    // Clearing screen (only the selected planes)
     fn cls(&mut self) {
-        self.screen_buffer = vec![vec![[0.1, 0.1, 0.1, 1.0]; 32]; 68];
+        self.screen_buffer.clear(self.planes);
+    }
+
+    // Width of the screen in the current resolution
+    fn screen_width(&self) -> usize {
+        self.screen_buffer.width()
+    }
+
+    // Height of the screen in the current resolution
+    fn screen_height(&self) -> usize {
+        self.screen_buffer.height()
+    }
+
+    // Switching between low and high resolution, this clears all the planes
+    fn set_hires(&mut self, hires: bool) {
+        self.screen_buffer = if hires {
+            Framebuffer::hires()
+        } else {
+            Framebuffer::lores()
+        };
+    }
+
+    // Scroll the selected planes by dx pixels right and dy pixels down
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        self.screen_buffer.scroll(self.planes, dx, dy);
+    }
+
+    // Select the planes affected by drawing, clearing and scrolling
+    fn plane(&mut self, planes: u8) {
+        self.planes = planes & 0b11;
     }
 
     // Return from subroutine
-    fn ret(&mut self) {
-        let curr_stack_val = self.stack[(self.stack.len() - 1) as usize];
-        if curr_stack_val != 0 {
-            self.pc = curr_stack_val;
-            self.stack.pop();
+    fn ret(&mut self) -> Result<(), CpuError> {
+        if self.sp == 0 {
+            return Err(CpuError::StackUnderflow {
+                pc: self.curr_pc,
+                opcode: self.curr_opcode,
+            });
         }
+
+        self.sp -= 1;
+        self.pc = self.stack[self.sp];
+        Ok(())
     }
 
     // Call subroutine at the given adress
-    fn call(&mut self, adress: u16) {
-        self.sp = self.sp + 1;
-        self.stack.push(self.pc);
+    fn call(&mut self, adress: u16) -> Result<(), CpuError> {
+        if self.sp >= self.stack.len() {
+            return Err(CpuError::StackOverflow {
+                pc: self.curr_pc,
+                opcode: self.curr_opcode,
+            });
+        }
+
+        self.stack[self.sp] = self.pc;
+        self.sp += 1;
         self.pc = adress;
+        Ok(())
     }
 
     // Loading value in register
@@ -316,74 +584,132 @@ impl Cpu {
         self.i_register = val;
     }
 
+    // Loading the 16 bits value following the opcode in I (XO-CHIP F000 NNNN)
+    fn ld_i_long(&mut self) -> Result<(), CpuError> {
+        let adress = self.pc as usize;
+        self.i_register = ((self.read(adress)? as u16) << 8) | self.read(adress + 1)? as u16;
+        self.pc = self.pc.wrapping_add(2);
+        Ok(())
+    }
+
+    // Skip the next instruction, F000 NNNN being 4 bytes long
+    fn skip(&mut self) {
+        let adress = self.pc as usize;
+        let long = self.ram.get(adress) == Some(&0xF0) && self.ram.get(adress + 1) == Some(&0x00);
+        self.pc = self.pc.wrapping_add(if long { 4 } else { 2 });
+    }
+
     // Storing random number anded with a value in register
     fn rnd_vx(&mut self, index: u8, val: u8) {
-        let mut rng = rand::thread_rng();
-        let random_val: u8 = rng.gen_range(0..255);
+        let random_val = self.rng.next_u8();
         self.registers[index as usize] = random_val & val;
     }
 
     // Check if Vx is equal to val and increment PC by 2 if this is true
     fn se_vx(&mut self, index: u8, val: u8) {
         if self.registers[index as usize] == val {
-            self.pc = self.pc + 2;
+            self.skip();
         }
     }
 
     // Check if Vx is not equal to val and increment PC by 2 if this is true
     fn sne_vx(&mut self, index: u8, val: u8) {
         if self.registers[index as usize] != val {
-            self.pc = self.pc + 2;
+            self.skip();
         }
     }
 
     // Check if Vx is not equal to Vy and increment PC by 2 if this is true
     fn se_vx_vy(&mut self, x: u8, y: u8) {
         if self.registers[x as usize] == self.registers[y as usize] {
-            self.pc = self.pc + 2;
+            self.skip();
         }
     }
 
-    // For drawing on screen
-    fn drw_vx_vy(&mut self, x: u8, y: u8, n: u8) {
-        // Position where to begin rendering the curretn sprite
-        let posx = self.registers[x as usize];
-        let posy = self.registers[y as usize];
+    // For drawing on screen (sprites are XORed onto the screen, VF is set on collision)
+    fn drw_vx_vy(&mut self, x: u8, y: u8, n: u8) -> Result<(), CpuError> {
+        // DXY0 draws a 16x16 sprite made of two bytes per row
+        let (width, height) = if n == 0 { (16, 16) } else { (8, n as usize) };
+
+        // Position where to begin rendering the current sprite, wrapped to the screen size
+        let posx = self.registers[x as usize] as usize % self.screen_width();
+        let posy = self.registers[y as usize] as usize % self.screen_height();
+
+        // VF is set if any lit pixel gets turned off by this sprite
+        let mut collision = false;
 
-        // For checking collision (TODO: add collision check)
-        self.registers[15] = 0;
+        // Each selected plane gets its own sprite, stored one after the other from I
+        let mut adress = self.i_register as usize;
+        for plane in [0b01, 0b10] {
+            if self.planes & plane != 0 {
+                collision |= self.draw_sprite(plane, adress, posx, posy, width, height)?;
+                adress += width / 8 * height;
+            }
+        }
+        self.registers[15] = collision as u8;
+
+        // Nothing else runs until the next frame
+        self.vblank_wait = self.quirks.display_wait;
+        Ok(())
+    }
+
+    // XOR a sprite read at adress onto one plane, returns if a lit pixel got turned off
+    fn draw_sprite(
+        &mut self,
+        plane: u8,
+        adress: usize,
+        posx: usize,
+        posy: usize,
+        width: usize,
+        height: usize,
+    ) -> Result<bool, CpuError> {
+        let screen_width = self.screen_width();
+        let screen_height = self.screen_height();
+        let mut collision = false;
 
         // Looping througth hight
-        for i in 0..n {
-            // Getting the current byte pointed at I + current row
-            let adress: u16 = self.i_register + i as u16;
-            let byte = self.ram[adress as usize];
-
-            // Looping throught columns of 8 pixels (each bit is a pixel, 1 = black, 0 = white)
-            for j in (0..8).rev() {
-                let a = byte & (1 << j);
-                if (a >> j) == 1 {
-                    self.screen_buffer[((7 - j) + posx) as usize][(posy + i) as usize] = BLACK;
-                } else {
-                    self.screen_buffer[((7 - j) + posx) as usize][(posy + i) as usize] = WHITE;
+        for i in 0..height {
+            // Rows past the bottom edge are either clipped or wrapped to the top
+            let mut row = posy + i;
+            if row >= screen_height {
+                if self.quirks.clip_sprites {
+                    break;
                 }
+                row %= screen_height;
+            }
+
+            // Getting the bits of the current row
+            let row_adress = adress + i * width / 8;
+            let mut bits = self.read(row_adress)? as u16;
+            if width == 16 {
+                bits = (bits << 8) | self.read(row_adress + 1)? as u16;
+            }
+
+            // Looping throught columns (each set bit flips a pixel)
+            for j in 0..width {
+                if bits & (1 << (width - 1 - j)) == 0 {
+                    continue;
+                }
+
+                // Columns past the right edge are either clipped or wrapped to the left
+                let mut col = posx + j;
+                if col >= screen_width {
+                    if self.quirks.clip_sprites {
+                        break;
+                    }
+                    col %= screen_width;
+                }
+
+                collision |= self.screen_buffer.toggle(plane, col, row);
             }
         }
+
+        Ok(collision)
     }
 
-    // Add val to current Vx and store it in Vx
+    // Add val to current Vx and store it in Vx, wrapping around without touching Vf
     fn add_vx(&mut self, index: u8, val: u8) {
-        match (self.registers[index as usize]).checked_add(val) {
-            Some(v) => {
-                self.registers[index as usize] = v;
-            }
-            None => {
-                println!("Overflow occured in add_vx function!");
-                println!("PC={:#04x}", self.pc - 2);
-                println!("Opcode={:#04x}", self.curr_opcode);
-                std::process::exit(1);
-            }
-        };
+        self.registers[index as usize] = self.registers[index as usize].wrapping_add(val);
     }
 
     // Jump instruction
@@ -393,39 +719,40 @@ impl Cpu {
 
     // Setting Vx to the value of dt
     fn ld_vx_dt(&mut self, index: u8) {
-        self.registers[index as usize] = self.delta_timer as u8;
+        self.registers[index as usize] = self.timers.delay;
     }
 
-    // Wait for keypress ans set the value of the key to Vx
+    // Wait for keypress and set the value of the key to Vx
     fn ld_vx_k(&mut self, index: u8) {
-        let key = 0;
-        self.registers[index as usize] = key;
+        self.key_wait = Some(KeyWait {
+            register: index,
+            pressed: None,
+        });
     }
 
     // Add the content of Vx to I and store it in I
     fn add_i_vx(&mut self, index: u8) {
-        self.i_register = self.i_register + self.registers[index as usize] as u16;
+        self.i_register = self
+            .i_register
+            .wrapping_add(self.registers[index as usize] as u16);
     }
 
     // Store the content of Vx to dt
     fn ld_dt_vx(&mut self, index: u8) {
-        self.delta_timer = self.registers[index as usize] as u16;
+        self.timers.delay = self.registers[index as usize];
     }
 
-    // Store the content of Vx to dt
+    // Store the content of Vx to st
     fn ld_st_vx(&mut self, index: u8) {
-        self.sub_timer = self.registers[index as usize] as u16;
+        self.timers.sound = self.registers[index as usize];
     }
 
     // Add Vx and Vy and store it in Vx, Vf is set if overflow
     fn add_vx_vy(&mut self, x: u8, y: u8) {
-        let val: u16 = self.registers[x as usize] as u16 + self.registers[y as usize] as u16;
-        if val > 0xFF {
-            self.registers[15] = 1;
-        } else {
-            self.registers[15] = 0;
-        }
-        self.registers[x as usize] = (val & 0xFF) as u8;
+        let (val, overflow) =
+            self.registers[x as usize].overflowing_add(self.registers[y as usize]);
+        self.registers[x as usize] = val;
+        self.registers[15] = overflow as u8;
     }
 
     // Loading value of Vy in Vx
@@ -435,131 +762,720 @@ impl Cpu {
 
     // Logical or value of Vy with Vx and store it in Vx
     fn or_vx_vy(&mut self, x: u8, y: u8) {
-        self.registers[x as usize] = self.registers[x as usize] | self.registers[y as usize];
+        self.registers[x as usize] |= self.registers[y as usize];
+        self.reset_vf();
     }
 
     // Logical xor value of Vy with Vx and store it in Vx
     fn xor_vx_vy(&mut self, x: u8, y: u8) {
-        self.registers[x as usize] = self.registers[x as usize] ^ self.registers[y as usize];
+        self.registers[x as usize] ^= self.registers[y as usize];
+        self.reset_vf();
     }
 
     // Logical and value of Vy with Vx and store it in Vx
     fn and_vx_vy(&mut self, x: u8, y: u8) {
-        self.registers[x as usize] = self.registers[x as usize] & self.registers[y as usize];
+        self.registers[x as usize] &= self.registers[y as usize];
+        self.reset_vf();
     }
 
-    // Substract value of Vy to Vx and store it in Vx, set Vf accordingly
+    // Substract value of Vy to Vx and store it in Vx, Vf is set if there is no borrow
     fn sub_vx_vy(&mut self, x: u8, y: u8) {
-        if self.registers[x as usize] > self.registers[y as usize] {
-            self.registers[15] = 1;
-        } else {
-            self.registers[15] = 0;
-        }
-
-        self.registers[x as usize] = self.registers[x as usize] - self.registers[y as usize];
+        let (val, borrow) = self.registers[x as usize].overflowing_sub(self.registers[y as usize]);
+        self.registers[x as usize] = val;
+        self.registers[15] = !borrow as u8;
     }
 
-    // Substract value of Vx to Vy and store it in Vx, set Vf accordingly
+    // Substract value of Vx to Vy and store it in Vx, Vf is set if there is no borrow
     fn subn_vx_vy(&mut self, x: u8, y: u8) {
-        if self.registers[x as usize] < self.registers[y as usize] {
-            self.registers[15] = 1;
-        } else {
-            self.registers[15] = 0;
-        }
-
-        self.registers[x as usize] = self.registers[y as usize] - self.registers[x as usize];
+        let (val, borrow) = self.registers[y as usize].overflowing_sub(self.registers[x as usize]);
+        self.registers[x as usize] = val;
+        self.registers[15] = !borrow as u8;
     }
 
-    // Shift right Vx
-    fn shr_vx(&mut self, x: u8) {
-        let val = self.registers[x as usize] & 0b00000001;
-
-        if val == 1 {
-            self.registers[15] = 1;
-        } else {
-            self.registers[15] = 0;
-        }
-
-        self.registers[x as usize] = self.registers[x as usize] >> 1;
+    // Shift right Vx (or Vy, depending on the quirks), Vf gets the bit shifted out
+    fn shr_vx(&mut self, x: u8, y: u8) {
+        let src = if self.quirks.shift_uses_vy { y } else { x };
+        let val = self.registers[src as usize];
+        self.registers[x as usize] = val >> 1;
+        self.registers[15] = val & 0b00000001;
     }
 
-    // Shift left Vx
-    fn shl_vx(&mut self, x: u8) {
-        let val = self.registers[x as usize] & 0b10000000;
+    // Shift left Vx (or Vy, depending on the quirks), Vf gets the bit shifted out
+    fn shl_vx(&mut self, x: u8, y: u8) {
+        let src = if self.quirks.shift_uses_vy { y } else { x };
+        let val = self.registers[src as usize];
+        self.registers[x as usize] = val << 1;
+        self.registers[15] = val >> 7;
+    }
 
-        if val == 1 {
-            self.registers[15] = 1;
-        } else {
+    // Reset Vf after a logical operation on platforms doing so
+    fn reset_vf(&mut self) {
+        if self.quirks.logic_resets_vf {
             self.registers[15] = 0;
         }
-
-        self.registers[x as usize] = self.registers[x as usize] << 1;
     }
 
     // Skip next instruction if Vx != Vy
     fn sne_vx_vy(&mut self, x: u8, y: u8) {
         if self.registers[x as usize] != self.registers[y as usize] {
-            self.pc = self.pc + 2;
+            self.skip();
         }
     }
 
-    // Jump to location adress + v0
+    // Jump to location adress + v0 (or adress + Vx for BXNN, depending on the quirks)
     fn jp_v0(&mut self, adress: u16) {
-        self.pc = adress + self.registers[0] as u16;
+        let index = if self.quirks.jump_uses_vx {
+            (adress >> 8) as usize
+        } else {
+            0
+        };
+        self.pc = adress + self.registers[index] as u16;
     }
 
     // Skip next instruction if key with the value of Vx is pressed
     fn skp_vx(&mut self, index: u8) {
-        if self.keys[index as usize] == true {
-            self.pc = self.pc + 2;
-        } else {
-            self.pc = self.pc - 2;
+        let key = self.registers[index as usize] & 0x0F;
+        if self.keys[key as usize] {
+            self.skip();
         }
     }
 
-    // Skip next instruction if key with the value of Vx is pressed
+    // Skip next instruction if key with the value of Vx is not pressed
     fn sknp_vx(&mut self, index: u8) {
-        if self.keys[index as usize] == false {
-            self.pc = self.pc + 2;
-        } else {
-            self.pc = self.pc - 2;
+        let key = self.registers[index as usize] & 0x0F;
+        if !self.keys[key as usize] {
+            self.skip();
         }
     }
 
     // BDC
-    fn ld_b_vx(&mut self, index: u8) {
-        self.ram[self.i_register as usize] = (self.registers[index as usize] / 100) as u8;
-        self.ram[(self.i_register + 1) as usize] =
-            ((self.registers[index as usize] % 100) / 10) as u8;
-        self.ram[(self.i_register + 2) as usize] = (self.registers[index as usize] % 10) as u8;
+    fn ld_b_vx(&mut self, index: u8) -> Result<(), CpuError> {
+        let val = self.registers[index as usize];
+        let adress = self.i_register as usize;
+        self.write(adress, val / 100)?;
+        self.write(adress + 1, (val % 100) / 10)?;
+        self.write(adress + 2, val % 10)
     }
 
     // Copy regiters v0 to Vx values to memory starting at I
-    fn ld_i_vx(&mut self, index: u8) {
-        for i in 0..(index + 1) {
-            self.ram[(self.i_register + i as u16) as usize] = self.registers[i as usize];
+    fn ld_i_vx(&mut self, index: u8) -> Result<(), CpuError> {
+        for i in 0..=index as usize {
+            self.write(self.i_register as usize + i, self.registers[i])?;
         }
+        self.increment_i(index);
+        Ok(())
     }
 
     // Load regiters v0 to Vx values from memory starting at I
-    fn ld_vx_i(&mut self, index: u8) {
-        for i in 0..(index + 1) {
-            self.registers[i as usize] = self.ram[(self.i_register + i as u16) as usize];
+    fn ld_vx_i(&mut self, index: u8) -> Result<(), CpuError> {
+        for i in 0..=index as usize {
+            self.registers[i] = self.read(self.i_register as usize + i)?;
+        }
+        self.increment_i(index);
+        Ok(())
+    }
+
+    // Move I after FX55/FX65 on platforms doing so
+    fn increment_i(&mut self, index: u8) {
+        match self.quirks.memory_increment {
+            MemoryIncrement::None => {}
+            MemoryIncrement::X => self.i_register = self.i_register.wrapping_add(index as u16),
+            MemoryIncrement::XPlusOne => {
+                self.i_register = self.i_register.wrapping_add(index as u16 + 1)
+            }
         }
     }
 
-    // Getting the current screen buffer
-    pub fn get_scree_buffer(&mut self) -> Vec<Vec<[f32; 4]>> {
-        let mut tmp_buffer: Vec<Vec<[f32; 4]>> = vec![vec![[0.1, 0.1, 0.1, 1.0]; 32]; 64];
-        for i in 0..64 {
-            for j in 0..32 {
-                tmp_buffer[i as usize][j as usize] = self.screen_buffer[i as usize][j as usize];
+    // Copy registers Vx to Vy (in either order) to memory starting at I, I is left untouched
+    fn ld_i_vx_vy(&mut self, x: u8, y: u8) -> Result<(), CpuError> {
+        for (offset, index) in Self::register_range(x, y).into_iter().enumerate() {
+            self.write(self.i_register as usize + offset, self.registers[index])?;
+        }
+        Ok(())
+    }
+
+    // Load registers Vx to Vy (in either order) from memory starting at I, I is left untouched
+    fn ld_vx_vy_i(&mut self, x: u8, y: u8) -> Result<(), CpuError> {
+        for (offset, index) in Self::register_range(x, y).into_iter().enumerate() {
+            self.registers[index] = self.read(self.i_register as usize + offset)?;
+        }
+        Ok(())
+    }
+
+    // Registers indexes from Vx to Vy, going down if y is lower than x
+    fn register_range(x: u8, y: u8) -> Vec<usize> {
+        let (x, y) = (x as usize, y as usize);
+        if x <= y {
+            (x..=y).collect()
+        } else {
+            (y..=x).rev().collect()
+        }
+    }
+
+    // Load the 16 bytes audio pattern buffer from memory starting at I
+    fn ld_audio_i(&mut self) -> Result<(), CpuError> {
+        let mut pattern = [0; 16];
+        for (i, byte) in pattern.iter_mut().enumerate() {
+            *byte = self.read(self.i_register as usize + i)?;
+        }
+        self.audio_pattern = Some(pattern);
+        Ok(())
+    }
+
+    // Set the playback pitch of the audio pattern to Vx
+    fn ld_pitch_vx(&mut self, index: u8) {
+        self.pitch = self.registers[index as usize];
+    }
+
+    // If the buzzer sounds, while the sound timer is non-zero
+    pub fn sound_active(&self) -> bool {
+        self.timers.sound > 0
+    }
+
+    // Getting the XO-CHIP audio pattern buffer, if the program loaded one
+    pub fn audio_pattern(&self) -> Option<&[u8; 16]> {
+        self.audio_pattern.as_ref()
+    }
+
+    // Getting the XO-CHIP audio pattern pitch
+    pub fn pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    // Getting the current screen, 64x32 or 128x64 in high resolution
+    pub fn framebuffer(&self) -> &Framebuffer {
+        &self.screen_buffer
+    }
+
+    // Getting the PC
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    // Getting the SP, the number of return adresses on the stack
+    pub fn sp(&self) -> usize {
+        self.sp
+    }
+
+    // Getting the return adresses on the stack, the innermost last
+    pub fn stack(&self) -> &[u16] {
+        &self.stack[..self.sp]
+    }
+
+    // Getting the registers V0-VF
+    pub fn registers(&self) -> &[u8] {
+        &self.registers
+    }
+
+    // Getting the I register
+    pub fn i_register(&self) -> u16 {
+        self.i_register
+    }
+
+    // Getting the delay timer
+    pub fn delay_timer(&self) -> u8 {
+        self.timers.delay
+    }
+
+    // Getting the sound timer
+    pub fn sound_timer(&self) -> u8 {
+        self.timers.sound
+    }
+
+    // Getting the whole memory
+    pub fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    // If execution is halted until FX0A gets its key
+    pub fn waiting_for_key(&self) -> bool {
+        self.key_wait.is_some()
+    }
+
+    // Changing the PC, e.g. from a debugger
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    // Changing a register V0-VF
+    pub fn set_register(&mut self, index: u8, val: u8) {
+        self.registers[(index & 0x0F) as usize] = val;
+    }
+
+    // Changing the I register
+    pub fn set_i_register(&mut self, val: u16) {
+        self.i_register = val;
+    }
+
+    // Changing the delay timer
+    pub fn set_delay_timer(&mut self, val: u8) {
+        self.timers.delay = val;
+    }
+
+    // Changing the sound timer
+    pub fn set_sound_timer(&mut self, val: u8) {
+        self.timers.sound = val;
+    }
+
+    // Writing bytes to memory starting at an adress, fails if they don't all fit
+    pub fn poke(&mut self, adress: usize, bytes: &[u8]) -> Result<(), CpuError> {
+        if adress + bytes.len() > self.ram.len() {
+            return Err(CpuError::MemoryOutOfBounds {
+                pc: self.curr_pc,
+                opcode: self.curr_opcode,
+                adress: adress + bytes.len() - 1,
+            });
+        }
+        self.ram[adress..adress + bytes.len()].copy_from_slice(bytes);
+        Ok(())
+    }
+
+    // Set I to the location of the font sprite for the digit in Vx
+    fn ld_f_vx(&mut self, index: u8) {
+        let digit = (self.registers[index as usize] & 0x0F) as u16;
+        self.i_register = FONT_ADDRESS + digit * GLYPH_SIZE;
+    }
+
+    // Set I to the location of the big font sprite for the digit in Vx
+    fn ld_hf_vx(&mut self, index: u8) {
+        let digit = (self.registers[index as usize] & 0x0F) as u16;
+        self.i_register = BIG_FONT_ADDRESS + digit * BIG_GLYPH_SIZE;
+    }
+
+    // Store registers v0 to Vx in the RPL user flags
+    fn ld_r_vx(&mut self, index: u8) {
+        let count = index as usize + 1;
+        self.rpl_flags[..count].copy_from_slice(&self.registers[..count]);
+    }
+
+    // Load registers v0 to Vx from the RPL user flags
+    fn ld_vx_r(&mut self, index: u8) {
+        let count = index as usize + 1;
+        self.registers[..count].copy_from_slice(&self.rpl_flags[..count]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A CPU with a program loaded
+    fn cpu(rom: &[u8], quirks: Quirks) -> Cpu {
+        Cpu::new(rom, &Font::Chip8, quirks).unwrap()
+    }
+
+    // Execute a number of instructions, ending the frame when a draw waits for it
+    fn steps(cpu: &mut Cpu, count: usize) {
+        let mut executed = 0;
+        while executed < count {
+            match cpu.run().unwrap() {
+                StepOutcome::Executed => executed += 1,
+                StepOutcome::WaitingForVblank => cpu.tick_timers(),
+                outcome => panic!("Unexpected {:?}", outcome),
+            }
+        }
+    }
+
+    // Run until the program jumps to itself
+    fn finish(cpu: &mut Cpu) {
+        for _ in 0..1000 {
+            let pc = cpu.pc;
+            steps(cpu, 1);
+            if cpu.pc == pc {
+                return;
+            }
+        }
+        panic!("The program didn't end");
+    }
+
+    // Run a program until it jumps to itself
+    fn run(rom: &[u8], quirks: Quirks) -> Cpu {
+        let mut cpu = cpu(rom, quirks);
+        finish(&mut cpu);
+        cpu
+    }
+
+    // Run a program until it fails
+    fn failure(rom: &[u8], quirks: Quirks) -> CpuError {
+        let mut cpu = cpu(rom, quirks);
+        for _ in 0..1000 {
+            if let Err(e) = cpu.run() {
+                return e;
             }
+            cpu.tick_timers();
         }
-        return tmp_buffer;
+        panic!("The program didn't fail");
     }
 
-    fn ld_f_vx(&mut self) {
-        // TODO
+    // The lit pixels, row by row
+    fn lit(cpu: &Cpu) -> Vec<(usize, usize)> {
+        let screen = &cpu.screen_buffer;
+        let mut pixels = Vec::new();
+        for y in 0..screen.height() {
+            for x in 0..screen.width() {
+                if screen.pixel(x, y) != 0 {
+                    pixels.push((x, y));
+                }
+            }
+        }
+        pixels
+    }
+
+    // Sprites
+
+    #[test]
+    fn sprites_are_xored_and_set_vf_on_collision() {
+        // Two pixels drawn at x 0 then at x 1, v2 := vf between the draws
+        let rom = [
+            0xA2, 0x0C, 0xD0, 0x11, 0x82, 0xF0, 0x70, 0x01, 0xD0, 0x11, 0x12, 0x0A, 0xC0,
+        ];
+        let cpu = run(&rom, Quirks::vip());
+        assert_eq!(lit(&cpu), [(0, 0), (2, 0)]);
+        assert_eq!(cpu.registers[2], 0);
+        assert_eq!(cpu.registers[15], 1);
+
+        // Drawing twice at the same place erases the sprite
+        let rom = [0xA2, 0x08, 0xD0, 0x11, 0xD0, 0x11, 0x12, 0x06, 0xC0];
+        let cpu = run(&rom, Quirks::vip());
+        assert!(lit(&cpu).is_empty());
+        assert_eq!(cpu.registers[15], 1);
+    }
+
+    #[test]
+    fn sprites_are_clipped_or_wrapped_at_the_edges() {
+        // A 4x2 sprite at (62, 31)
+        let rom = [
+            0x60, 0x3E, 0x61, 0x1F, 0xA2, 0x0A, 0xD0, 0x12, 0x12, 0x08, 0xF0, 0xF0,
+        ];
+        let cpu = run(&rom, Quirks::vip());
+        assert_eq!(lit(&cpu), [(62, 31), (63, 31)]);
+
+        let quirks = Quirks {
+            clip_sprites: false,
+            ..Quirks::vip()
+        };
+        let cpu = run(&rom, quirks);
+        let wrapped = [
+            (0, 0),
+            (1, 0),
+            (62, 0),
+            (63, 0),
+            (0, 31),
+            (1, 31),
+            (62, 31),
+            (63, 31),
+        ];
+        assert_eq!(lit(&cpu), wrapped);
+
+        // The starting position always wraps
+        let rom = [
+            0x60, 0x42, 0x61, 0x21, 0xA2, 0x0A, 0xD0, 0x11, 0x12, 0x08, 0x80,
+        ];
+        let cpu = run(&rom, Quirks::vip());
+        assert_eq!(lit(&cpu), [(2, 1)]);
+    }
+
+    #[test]
+    fn font_sprites() {
+        // i := hex v0, then i := bighex v0
+        let rom = [0x60, 0x07, 0xF0, 0x29, 0x12, 0x04];
+        let cpu = run(&rom, Quirks::vip());
+        assert_eq!(cpu.i_register, FONT_ADDRESS + 7 * GLYPH_SIZE);
+
+        let rom = [0x60, 0x07, 0xF0, 0x30, 0x12, 0x04];
+        let cpu = run(&rom, Quirks::schip11());
+        assert_eq!(cpu.i_register, BIG_FONT_ADDRESS + 7 * BIG_GLYPH_SIZE);
+    }
+
+    // Keypad
+
+    #[test]
+    fn fx0a_waits_for_a_press_then_a_release() {
+        let mut cpu = cpu(&[0xF3, 0x0A, 0x12, 0x02], Quirks::vip());
+
+        // A key held before the wait doesn't count
+        cpu.set_key(5, true);
+        assert_eq!(cpu.run(), Ok(StepOutcome::Executed));
+        assert_eq!(cpu.run(), Ok(StepOutcome::WaitingForKey));
+        cpu.set_key(5, false);
+        assert_eq!(cpu.run(), Ok(StepOutcome::WaitingForKey));
+
+        // The press alone doesn't end the wait, its release does
+        cpu.set_key(7, true);
+        assert_eq!(cpu.run(), Ok(StepOutcome::WaitingForKey));
+        cpu.set_key(2, false);
+        assert!(cpu.key_wait.is_some());
+        cpu.set_key(7, false);
+        assert!(cpu.key_wait.is_none());
+        assert_eq!(cpu.registers[3], 7);
+        assert_eq!(cpu.run(), Ok(StepOutcome::Executed));
+        assert_eq!(cpu.pc, 0x202);
+    }
+
+    #[test]
+    fn keypad_skips() {
+        // v1 := 1 unless key v0 is down, v2 := 1 unless key v0 is up
+        let rom = [
+            0x60, 0x05, 0xE0, 0x9E, 0x61, 0x01, 0xE0, 0xA1, 0x62, 0x01, 0x12, 0x0A,
+        ];
+        let mut pressed = cpu(&rom, Quirks::vip());
+        pressed.set_key(5, true);
+        finish(&mut pressed);
+        assert_eq!(pressed.registers[1..3], [0, 1]);
+
+        let released = run(&rom, Quirks::vip());
+        assert_eq!(released.registers[1..3], [1, 0]);
+    }
+
+    // Quirks
+
+    #[test]
+    fn shift_quirk() {
+        // v0 := 0x10, v1 := 0x81, then v0 >>= v1 or v0 <<= v1
+        let right = [0x60, 0x10, 0x61, 0x81, 0x80, 0x16, 0x12, 0x06];
+        let left = [0x60, 0x10, 0x61, 0x81, 0x80, 0x1E, 0x12, 0x06];
+        let registers = |rom: &[u8], quirks| {
+            let cpu = run(rom, quirks);
+            (cpu.registers[0], cpu.registers[15])
+        };
+        assert_eq!(registers(&right, Quirks::vip()), (0x40, 1));
+        assert_eq!(registers(&right, Quirks::chip48()), (0x08, 0));
+        assert_eq!(registers(&left, Quirks::vip()), (0x02, 1));
+        assert_eq!(registers(&left, Quirks::chip48()), (0x20, 0));
+    }
+
+    #[test]
+    fn memory_increment_quirk() {
+        // i := 0x300, save v1 or load v1
+        for op in [0x55, 0x65] {
+            let rom = [0xA3, 0x00, 0xF1, op, 0x12, 0x04];
+            assert_eq!(run(&rom, Quirks::vip()).i_register, 0x302);
+            assert_eq!(run(&rom, Quirks::chip48()).i_register, 0x301);
+            assert_eq!(run(&rom, Quirks::schip11()).i_register, 0x300);
+        }
+    }
+
+    #[test]
+    fn jump_quirk() {
+        // v0 := 4, v2 := 8, jump0 0x20A
+        let rom = [0x60, 0x04, 0x62, 0x08, 0xB2, 0x0A];
+        let mut vip = cpu(&rom, Quirks::vip());
+        steps(&mut vip, 3);
+        assert_eq!(vip.pc, 0x20E);
+
+        let mut chip48 = cpu(&rom, Quirks::chip48());
+        steps(&mut chip48, 3);
+        assert_eq!(chip48.pc, 0x212);
+    }
+
+    #[test]
+    fn logic_quirk() {
+        // vf := 5, then v0 |= v1, v0 &= v1 or v0 ^= v1
+        for op in [0x11, 0x12, 0x13] {
+            let rom = [0x6F, 0x05, 0x80, op, 0x12, 0x04];
+            assert_eq!(run(&rom, Quirks::vip()).registers[15], 0);
+            assert_eq!(run(&rom, Quirks::chip48()).registers[15], 5);
+        }
+    }
+
+    #[test]
+    fn display_wait_quirk() {
+        let rom = [0xD0, 0x01, 0x60, 0x01, 0x12, 0x04];
+        let mut vip = cpu(&rom, Quirks::vip());
+        assert_eq!(vip.run(), Ok(StepOutcome::Executed));
+        assert_eq!(vip.run(), Ok(StepOutcome::WaitingForVblank));
+        vip.tick_timers();
+        assert_eq!(vip.run(), Ok(StepOutcome::Executed));
+
+        let mut chip48 = cpu(&rom, Quirks::chip48());
+        assert_eq!(chip48.run(), Ok(StepOutcome::Executed));
+        assert_eq!(chip48.run(), Ok(StepOutcome::Executed));
+    }
+
+    // SUPER-CHIP
+
+    #[test]
+    fn hires_and_16x16_sprites() {
+        // hires, then a 16x16 sprite at (0, 0)
+        let mut rom = vec![0x00, 0xFF, 0xA2, 0x08, 0xD0, 0x10, 0x12, 0x06];
+        rom.extend([0xFF; 32]);
+        let cpu = run(&rom, Quirks::schip11());
+        assert!(cpu.screen_buffer.is_hires());
+        let square: Vec<(usize, usize)> =
+            (0..16).flat_map(|y| (0..16).map(move |x| (x, y))).collect();
+        assert_eq!(lit(&cpu), square);
+
+        // Going back to low resolution clears the screen
+        let mut rom = vec![0x00, 0xFF, 0xA2, 0x0A, 0xD0, 0x10, 0x00, 0xFE, 0x12, 0x08];
+        rom.extend([0xFF; 32]);
+        let cpu = run(&rom, Quirks::schip11());
+        assert!(!cpu.screen_buffer.is_hires());
+        assert!(lit(&cpu).is_empty());
+    }
+
+    #[test]
+    fn scrolling() {
+        // A pixel at (0, 0), scrolled down 2, right 4 twice and left 4
+        let rom = [
+            0xA2, 0x0E, 0xD0, 0x11, 0x00, 0xC2, 0x00, 0xFB, 0x00, 0xFB, 0x00, 0xFC, 0x12, 0x0C,
+            0x80,
+        ];
+        let cpu = run(&rom, Quirks::schip11());
+        assert_eq!(lit(&cpu), [(4, 2)]);
+    }
+
+    #[test]
+    fn rpl_flags() {
+        // Save v0-v2, clear them, then load them back
+        let rom = [
+            0x60, 0x01, 0x61, 0x02, 0x62, 0x03, 0xF2, 0x75, 0x60, 0x00, 0x61, 0x00, 0x62, 0x00,
+            0xF2, 0x85, 0x12, 0x10,
+        ];
+        let cpu = run(&rom, Quirks::schip11());
+        assert_eq!(cpu.registers[..3], [1, 2, 3]);
+    }
+
+    #[test]
+    fn exit() {
+        let mut cpu = cpu(&[0x00, 0xFD, 0x12, 0x02], Quirks::schip11());
+        assert_eq!(cpu.run(), Ok(StepOutcome::Exit));
+        assert_eq!(cpu.run(), Ok(StepOutcome::Exit));
+        assert_eq!(cpu.pc, 0x202);
+    }
+
+    // XO-CHIP
+
+    #[test]
+    fn register_ranges() {
+        // Save v1-v3 at 0x300, v3-v1 at 0x310, then load v4-v6 from 0x300
+        let rom = [
+            0x61, 0x01, 0x62, 0x02, 0x63, 0x03, 0xA3, 0x00, 0x51, 0x32, 0xA3, 0x10, 0x53, 0x12,
+            0xA3, 0x00, 0x54, 0x63, 0x12, 0x12,
+        ];
+        let cpu = run(&rom, Quirks::xochip());
+        assert_eq!(cpu.ram[0x300..0x303], [1, 2, 3]);
+        assert_eq!(cpu.ram[0x310..0x313], [3, 2, 1]);
+        assert_eq!(cpu.registers[4..7], [1, 2, 3]);
+        assert_eq!(cpu.i_register, 0x300);
+    }
+
+    #[test]
+    fn long_i_load() {
+        let cpu = run(&[0xF0, 0x00, 0x12, 0x34, 0x12, 0x04], Quirks::xochip());
+        assert_eq!(cpu.i_register, 0x1234);
+
+        // Skips step over the whole 4 bytes instruction
+        let rom = [0x30, 0x00, 0xF0, 0x00, 0x12, 0x34, 0x61, 0x01, 0x12, 0x08];
+        let cpu = run(&rom, Quirks::xochip());
+        assert_eq!(cpu.i_register, 0);
+        assert_eq!(cpu.registers[1], 1);
+    }
+
+    #[test]
+    fn memory_size_quirk() {
+        // i := long 0x1234, then load v0
+        let rom = [0xF0, 0x00, 0x12, 0x34, 0xF0, 0x65, 0x12, 0x06];
+        assert_eq!(
+            failure(&rom, Quirks::vip()),
+            CpuError::MemoryOutOfBounds {
+                pc: 0x204,
+                opcode: 0xF065,
+                adress: 0x1234
+            }
+        );
+        assert_eq!(run(&rom, Quirks::xochip()).i_register, 0x1235);
+    }
+
+    #[test]
+    fn bitplanes() {
+        let rom = [
+            0xF3, 0x01, 0xA2, 0x12, 0xD0, 0x11, // both planes, two sprites drawn
+            0x82, 0xF0, 0xF1, 0x01, 0x00, 0xE0, // v2 := vf, first plane cleared
+            0xF2, 0x01, 0xD0, 0x11, 0x12, 0x10, // second plane drawn again
+            0x80, 0x80,
+        ];
+        let mut cpu = cpu(&rom, Quirks::xochip());
+        steps(&mut cpu, 3);
+        assert_eq!(cpu.screen_buffer.pixel(0, 0), 0b11);
+        steps(&mut cpu, 3);
+        assert_eq!(cpu.screen_buffer.pixel(0, 0), 0b10);
+        steps(&mut cpu, 2);
+        assert_eq!(cpu.screen_buffer.pixel(0, 0), 0);
+        assert_eq!(cpu.registers[2], 0);
+        assert_eq!(cpu.registers[15], 1);
+    }
+
+    #[test]
+    fn scrolling_up() {
+        // A pixel at (0, 2) scrolled up 1
+        let rom = [
+            0x61, 0x02, 0xA2, 0x0A, 0xD0, 0x11, 0x00, 0xD1, 0x12, 0x08, 0x80,
+        ];
+        let cpu = run(&rom, Quirks::xochip());
+        assert_eq!(lit(&cpu), [(0, 1)]);
+    }
+
+    #[test]
+    fn audio_pattern_and_pitch() {
+        let mut rom = vec![0xA2, 0x0A, 0xF0, 0x02, 0x60, 0x70, 0xF0, 0x3A, 0x12, 0x08];
+        rom.extend(0..16);
+        assert_eq!(cpu(&rom, Quirks::xochip()).audio_pattern, None);
+        let cpu = run(&rom, Quirks::xochip());
+        let pattern: Vec<u8> = (0..16).collect();
+        assert_eq!(cpu.audio_pattern.unwrap()[..], pattern[..]);
+        assert_eq!(cpu.pitch, 0x70);
+    }
+
+    // Stack
+
+    #[test]
+    fn calls_and_returns() {
+        // call 0x204, loop, then return
+        let cpu = run(&[0x22, 0x04, 0x12, 0x02, 0x00, 0xEE], Quirks::vip());
+        assert_eq!(cpu.sp, 0);
+        assert_eq!(cpu.pc, 0x202);
+    }
+
+    #[test]
+    fn calls_past_the_stack_depth_overflow() {
+        // A subroutine calling itself forever
+        let rom = [0x22, 0x00];
+        let overflow = CpuError::StackOverflow {
+            pc: 0x200,
+            opcode: 0x2200,
+        };
+        for depth in [12, 300] {
+            let quirks = Quirks {
+                stack_depth: depth,
+                ..Quirks::vip()
+            };
+            let mut cpu = cpu(&rom, quirks);
+            steps(&mut cpu, depth);
+            assert_eq!(cpu.sp, depth);
+            assert!(cpu.stack.iter().all(|adress| *adress == 0x202));
+            assert_eq!(cpu.run(), Err(overflow.clone()));
+        }
+    }
+
+    #[test]
+    fn returns_with_an_empty_stack_underflow() {
+        let underflow = CpuError::StackUnderflow {
+            pc: 0x200,
+            opcode: 0x00EE,
+        };
+        assert_eq!(failure(&[0x00, 0xEE], Quirks::vip()), underflow);
+    }
+
+    // Errors
+
+    #[test]
+    fn unknown_opcodes() {
+        for opcode in [
+            0x5121, 0x8128, 0x9121, 0xE1A2, 0xE19F, 0xF1FF, 0xF100, 0xF102,
+        ] {
+            let rom = u16::to_be_bytes(opcode);
+            let error = CpuError::UnknownOpcode { pc: 0x200, opcode };
+            assert_eq!(failure(&rom, Quirks::xochip()), error);
+        }
     }
 }
@@ -0,0 +1,613 @@
+// Importing useful modules
+use super::cpu::StepOutcome;
+use super::disasm::{self, Line, Syntax};
+use super::error::CpuError;
+use super::instruction::Variant;
+use super::machine::Machine;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, BufRead, Write};
+
+// Instructions shown before and after the PC by the disassembly
+const CONTEXT_INSTRUCTIONS: u16 = 5;
+
+// Bytes shown by a memory dump when not specified
+const DEFAULT_DUMP_SIZE: usize = 64;
+
+// Commands of the prompt
+const HELP: &str = "\
+break <adr>           (b)  set a breakpoint, lists them without an adress
+delete [adr]          (d)  remove a breakpoint, all of them without an adress
+step [n]              (s)  execute n instructions (1 by default)
+continue [frames]     (c)  run until a breakpoint, or for a number of frames
+finish                (f)  run until the current subroutine returns
+regs                  (r)  print the registers, I, the timers and the stack
+dump <adr> [len]      (x)  hex dump of the memory (64 bytes by default)
+disasm [adr] [n]      (l)  disassemble around the PC, or n instructions from an adress
+set <reg> <value>          change V0-VF, I, PC, DT or ST
+poke <adr> <bytes...>      write bytes to memory
+key <key> [down|up]        press then release a keypad key, or only press or release it
+labels                     list the labels of the program
+help                  (h)  show this help
+quit                  (q)  stop the emulation
+Adresses, values and keys are hexadecimal, counts are decimal. Adresses can also be label names.
+An empty line repeats the last command.";
+
+// What the frontend does once the prompt returns
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resume {
+    // Run the machine, until the debugger pauses it again
+    Continue,
+
+    // Stop the emulation
+    Quit,
+}
+
+// Interactive debugger reading commands on the standard input. Frontends call prompt() while the
+// machine is paused and run their frames with run_frame(), which pauses on breakpoints
+pub struct Debugger {
+    // Where the commands come from
+    input: Box<dyn BufRead>,
+
+    // Adresses stopping the execution
+    breakpoints: BTreeSet<u16>,
+
+    // Instructions executed since the last timers tick, so stepping keeps the frames timing
+    cycles: u32,
+
+    // Set when resuming, so the breakpoint at the PC doesn't pause again before it has executed
+    resuming: bool,
+
+    // Stack depth below which the execution pauses (run to return)
+    return_depth: Option<usize>,
+
+    // Frames left before the execution pauses (continue for a number of frames)
+    frames_left: Option<usize>,
+
+    // The last command, repeated by an empty line
+    last_command: String,
+
+    // Labels of the program by name, and by adress for the symbolic display
+    labels: BTreeMap<String, u16>,
+    symbols: BTreeMap<u16, String>,
+}
+
+// Debugger methods
+impl Debugger {
+    // A debugger reading commands on the standard input
+    pub fn new() -> Debugger {
+        Debugger::with_input(Box::new(io::stdin().lock()))
+    }
+
+    // A debugger reading commands from any input, e.g. a script
+    pub fn with_input(input: Box<dyn BufRead>) -> Debugger {
+        Debugger {
+            input,
+            breakpoints: BTreeSet::new(),
+            cycles: 0,
+            resuming: false,
+            return_depth: None,
+            frames_left: None,
+            last_command: String::new(),
+            labels: BTreeMap::new(),
+            symbols: BTreeMap::new(),
+        }
+    }
+
+    // Stop the execution before the instruction at an adress
+    pub fn add_breakpoint(&mut self, adress: u16) {
+        self.breakpoints.insert(adress);
+    }
+
+    // Labels of the program (e.g. compiled from an Octo source), usable as adresses and shown
+    // in the disassembly
+    pub fn set_labels(&mut self, labels: BTreeMap<String, u16>) {
+        self.symbols.clear();
+        for (name, adress) in &labels {
+            self.symbols.entry(*adress).or_insert_with(|| name.clone());
+        }
+        self.labels = labels;
+    }
+
+    // Remove a breakpoint, returns if there was one
+    pub fn remove_breakpoint(&mut self, adress: u16) -> bool {
+        self.breakpoints.remove(&adress)
+    }
+
+    // Read and execute commands until one resumes the machine or quits. The end of the input quits
+    pub fn prompt(&mut self, machine: &mut Machine) -> Resume {
+        self.print_location(machine);
+        loop {
+            print!("(rc8) ");
+            io::stdout().flush().ok();
+            let mut line = String::new();
+            match self.input.read_line(&mut line) {
+                Ok(0) | Err(_) => return Resume::Quit,
+                Ok(_) => {}
+            }
+
+            // An empty line repeats the last command
+            let line = line.trim();
+            if !line.is_empty() {
+                self.last_command = line.to_string();
+            }
+            let command = self.last_command.clone();
+            if command.is_empty() {
+                continue;
+            }
+            match self.execute(machine, &command) {
+                Ok(Some(resume)) => return resume,
+                Ok(None) => {}
+                Err(e) => println!("{}", e),
+            }
+        }
+    }
+
+    // Run one 60 Hz frame like Machine::run_frame, None when the debugger paused the machine in
+    // the middle of it. The next call finishes the frame
+    pub fn run_frame(&mut self, machine: &mut Machine) -> Result<Option<StepOutcome>, CpuError> {
+        if self.frames_left == Some(0) {
+            self.frames_left = None;
+            return Ok(None);
+        }
+        loop {
+            let pc = machine.cpu().pc();
+            if !self.resuming && self.breakpoints.contains(&pc) {
+                println!("Breakpoint at {}", self.describe(pc));
+                return Ok(None);
+            }
+
+            let (outcome, frame_end) = self.step(machine)?;
+            if let Some(depth) = self.return_depth {
+                if machine.cpu().sp() < depth {
+                    self.return_depth = None;
+                    println!("Returned to {}", self.describe(machine.cpu().pc()));
+                    return Ok(None);
+                }
+            }
+            if frame_end {
+                if let Some(frames) = &mut self.frames_left {
+                    *frames = frames.saturating_sub(1);
+                }
+                return Ok(Some(outcome));
+            }
+        }
+    }
+
+    // Execute one instruction, the timers tick when a frame worth of instructions has run or the
+    // CPU halts. Returns the outcome and if the frame has ended
+    fn step(&mut self, machine: &mut Machine) -> Result<(StepOutcome, bool), CpuError> {
+        let outcome = machine.step()?;
+        if matches!(outcome, StepOutcome::Executed | StepOutcome::Exit) {
+            self.resuming = false;
+        }
+        self.cycles += 1;
+        let frame_end =
+            outcome != StepOutcome::Executed || self.cycles >= machine.instructions_per_frame();
+        if frame_end {
+            machine.tick_timers();
+            self.cycles = 0;
+        }
+        Ok((outcome, frame_end))
+    }
+
+    // Execute a command, Some when the machine resumes or the emulation stops
+    fn execute(&mut self, machine: &mut Machine, command: &str) -> Result<Option<Resume>, String> {
+        let mut words = command.split_whitespace();
+        let name = words.next().unwrap_or("");
+        let args: Vec<&str> = words.collect();
+        match name {
+            "break" | "b" => match args.first() {
+                Some(adress) => {
+                    let adress = self.adress(adress)?;
+                    self.add_breakpoint(adress);
+                    println!("Breakpoint at {}", self.describe(adress));
+                }
+                None if self.breakpoints.is_empty() => println!("No breakpoints"),
+                None => {
+                    for adress in &self.breakpoints {
+                        println!("Breakpoint at {}", self.describe(*adress));
+                    }
+                }
+            },
+            "delete" | "d" => match args.first() {
+                Some(adress) => {
+                    let adress = self.adress(adress)?;
+                    if !self.remove_breakpoint(adress) {
+                        return Err(format!("No breakpoint at {:04X}", adress));
+                    }
+                }
+                None => self.breakpoints.clear(),
+            },
+            "step" | "s" => {
+                let count = parse_count(args.first(), 1)?;
+                self.step_instructions(machine, count);
+                self.print_location(machine);
+            }
+            "continue" | "c" => {
+                self.frames_left = match args.first() {
+                    Some(frames) => Some(parse_count(Some(frames), 0)?),
+                    None => None,
+                };
+                self.resuming = true;
+                return Ok(Some(Resume::Continue));
+            }
+            "finish" | "f" => {
+                let depth = machine.cpu().sp();
+                if depth == 0 {
+                    return Err("Not in a subroutine".to_string());
+                }
+                self.return_depth = Some(depth);
+                self.frames_left = None;
+                self.resuming = true;
+                return Ok(Some(Resume::Continue));
+            }
+            "regs" | "r" => print_registers(machine),
+            "dump" | "x" => {
+                let adress = self.adress(args.first().ok_or("Usage: dump <adr> [len]")?)?;
+                let size = parse_count(args.get(1), DEFAULT_DUMP_SIZE)?;
+                print_memory(machine, adress as usize, size);
+            }
+            "disasm" | "l" => match args.first() {
+                Some(adress) => {
+                    let count = parse_count(args.get(1), 2 * CONTEXT_INSTRUCTIONS as usize + 1)?;
+                    self.print_disassembly(machine, self.adress(adress)?, count);
+                }
+                None => {
+                    let start = machine.cpu().pc().saturating_sub(2 * CONTEXT_INSTRUCTIONS);
+                    self.print_disassembly(machine, start, 2 * CONTEXT_INSTRUCTIONS as usize + 1);
+                }
+            },
+            "set" => {
+                let (register, value) = match args[..] {
+                    [register, value] => (register.to_lowercase(), parse_hex(value)?),
+                    _ => return Err("Usage: set <reg> <value>".to_string()),
+                };
+                set_register(machine, &register, value)?;
+            }
+            "poke" => {
+                let adress = self.adress(args.first().ok_or("Usage: poke <adr> <bytes...>")?)?;
+                let bytes = args[1..]
+                    .iter()
+                    .map(|byte| parse_byte(byte))
+                    .collect::<Result<Vec<u8>, String>>()?;
+                machine
+                    .cpu_mut()
+                    .poke(adress as usize, &bytes)
+                    .map_err(|e| e.to_string())?;
+            }
+            "key" => {
+                let key = args
+                    .first()
+                    .map(|key| parse_hex(key))
+                    .transpose()?
+                    .filter(|key| *key < 16)
+                    .ok_or("Usage: key <0-F> [down|up]")? as u8;
+                match args.get(1) {
+                    Some(&"down") => machine.set_key(key, true),
+                    Some(&"up") => machine.set_key(key, false),
+                    None => {
+                        machine.set_key(key, true);
+                        machine.set_key(key, false);
+                    }
+                    Some(other) => return Err(format!("Expected down or up, not {}", other)),
+                }
+            }
+            "labels" if self.labels.is_empty() => println!("No labels"),
+            "labels" => {
+                for (name, adress) in &self.labels {
+                    println!("{:04X}  {}", adress, name);
+                }
+            }
+            "help" | "h" => println!("{}", HELP),
+            "quit" | "q" => return Ok(Some(Resume::Quit)),
+            _ => return Err(format!("Unknown command {}, try help", name)),
+        }
+        Ok(None)
+    }
+
+    // Execute a number of instructions, stopping early on a breakpoint or when the CPU halts
+    fn step_instructions(&mut self, machine: &mut Machine, count: usize) {
+        let mut executed = 0;
+        while executed < count {
+            match self.step(machine) {
+                Ok((StepOutcome::Executed, _)) => executed += 1,
+                Ok((StepOutcome::Exit, _)) => {
+                    println!("Program exited");
+                    return;
+                }
+                Ok((StepOutcome::WaitingForKey, _)) => {
+                    println!("Waiting for a key");
+                    return;
+                }
+                // No instruction ran, the CPU waits for the end of the frame after a draw
+                Ok((StepOutcome::WaitingForVblank, _)) => {}
+                Err(e) => {
+                    println!("Emulation stopped: {}", e);
+                    return;
+                }
+            }
+            let pc = machine.cpu().pc();
+            if executed < count && self.breakpoints.contains(&pc) {
+                println!("Breakpoint at {}", self.describe(pc));
+                return;
+            }
+        }
+    }
+
+    // Disassemble a number of instructions from an adress, marking the PC and the breakpoints
+    fn print_disassembly(&self, machine: &Machine, start: u16, count: usize) {
+        let pc = machine.cpu().pc();
+        for line in disassemble(machine, start, count) {
+            let marker = if line.adress == pc { '>' } else { ' ' };
+            let breakpoint = if self.breakpoints.contains(&line.adress) {
+                '*'
+            } else {
+                ' '
+            };
+            if let Some(name) = self.symbols.get(&line.adress) {
+                println!("{}:", name);
+            }
+            println!("{}{} {}", marker, breakpoint, line);
+        }
+    }
+
+    // The instruction at the PC, with the label it follows
+    fn print_location(&self, machine: &Machine) {
+        let pc = machine.cpu().pc();
+        for line in disassemble(machine, pc, 1) {
+            match self.symbol(pc) {
+                Some(symbol) => println!("{}  <{}>", line, symbol),
+                None => println!("{}", line),
+            }
+        }
+    }
+
+    // An adress from a label name or an hexadecimal number
+    fn adress(&self, text: &str) -> Result<u16, String> {
+        match self.labels.get(text) {
+            Some(adress) => Ok(*adress),
+            None => parse_adress(text),
+        }
+    }
+
+    // The closest label at or before an adress, with the offset from it
+    fn symbol(&self, adress: u16) -> Option<String> {
+        let (start, name) = self.symbols.range(..=adress).next_back()?;
+        Some(match adress - start {
+            0 => name.clone(),
+            offset => format!("{}+{:X}", name, offset),
+        })
+    }
+
+    // An adress followed by its symbol, if any
+    fn describe(&self, adress: u16) -> String {
+        match self.symbol(adress) {
+            Some(symbol) => format!("{:04X} <{}>", adress, symbol),
+            None => format!("{:04X}", adress),
+        }
+    }
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Disassemble a number of instructions from an adress, all the instructions the CPU runs are known
+fn disassemble(machine: &Machine, start: u16, count: usize) -> Vec<Line> {
+    let ram = machine.cpu().ram();
+    let start = (start as usize).min(ram.len());
+    let end = start.saturating_add(count.saturating_mul(4)).min(ram.len());
+    let mut lines = disasm::disassemble_rom(
+        &ram[start..end],
+        start as u16,
+        Syntax::Cowgod,
+        Variant::XoChip,
+    );
+    lines.truncate(count);
+    lines
+}
+
+// The registers, I, the timers and the return adresses
+fn print_registers(machine: &Machine) {
+    let cpu = machine.cpu();
+    println!(
+        "PC={:04X} I={:04X} SP={} DT={:02X} ST={:02X}",
+        cpu.pc(),
+        cpu.i_register(),
+        cpu.sp(),
+        cpu.delay_timer(),
+        cpu.sound_timer()
+    );
+    for (row, registers) in cpu.registers().chunks(8).enumerate() {
+        let registers: Vec<String> = registers
+            .iter()
+            .enumerate()
+            .map(|(i, value)| format!("V{:X}={:02X}", row * 8 + i, value))
+            .collect();
+        println!("{}", registers.join(" "));
+    }
+    let stack: Vec<String> = cpu.stack().iter().map(|a| format!("{:04X}", a)).collect();
+    println!("Stack: {}", stack.join(" "));
+}
+
+// Hex dump of the memory, 16 bytes per line followed by their ASCII characters
+fn print_memory(machine: &Machine, start: usize, size: usize) {
+    let ram = machine.cpu().ram();
+    let end = start.saturating_add(size).min(ram.len());
+    for line_start in (start..end).step_by(16) {
+        let bytes = &ram[line_start..(line_start + 16).min(end)];
+        let hex: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        let ascii: String = bytes
+            .iter()
+            .map(|&b| if b.is_ascii_graphic() { b as char } else { '.' })
+            .collect();
+        println!("{:04X}: {:<47}  {}", line_start, hex.join(" "), ascii);
+    }
+}
+
+// Change a register from its name: V0-VF, I, PC, DT or ST
+fn set_register(machine: &mut Machine, register: &str, value: u32) -> Result<(), String> {
+    let byte = || u8::try_from(value).map_err(|_| format!("{:X} doesn't fit in a byte", value));
+    let cpu = machine.cpu_mut();
+    match register {
+        "i" => cpu.set_i_register(parse_u16(value)?),
+        "pc" => cpu.set_pc(parse_u16(value)?),
+        "dt" => cpu.set_delay_timer(byte()?),
+        "st" => cpu.set_sound_timer(byte()?),
+        _ => {
+            let index = register
+                .strip_prefix('v')
+                .and_then(|index| u8::from_str_radix(index, 16).ok())
+                .filter(|index| *index < 16 && register.len() == 2)
+                .ok_or(format!("Unknown register {}", register))?;
+            cpu.set_register(index, byte()?);
+        }
+    }
+    Ok(())
+}
+
+// Hexadecimal number, with an optional 0x or # prefix
+fn parse_hex(text: &str) -> Result<u32, String> {
+    let digits = text
+        .strip_prefix("0x")
+        .or_else(|| text.strip_prefix('#'))
+        .unwrap_or(text);
+    u32::from_str_radix(digits, 16).map_err(|_| format!("Invalid hexadecimal number {}", text))
+}
+
+fn parse_u16(value: u32) -> Result<u16, String> {
+    u16::try_from(value).map_err(|_| format!("{:X} doesn't fit in 16 bits", value))
+}
+
+fn parse_adress(text: &str) -> Result<u16, String> {
+    parse_u16(parse_hex(text)?)
+}
+
+fn parse_byte(text: &str) -> Result<u8, String> {
+    let value = parse_hex(text)?;
+    u8::try_from(value).map_err(|_| format!("{:X} doesn't fit in a byte", value))
+}
+
+// Decimal count, the default when missing
+fn parse_count(text: Option<&&str>, default: usize) -> Result<usize, String> {
+    match text {
+        Some(text) => text.parse().map_err(|_| format!("Invalid count {}", text)),
+        None => Ok(default),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::font::Font;
+    use crate::chip8::quirks::Quirks;
+    use std::io::Cursor;
+
+    // 200: v0 := 1, 202: v1 := 2, 204: v2 := 3, 206: loop
+    const PROGRAM: [u8; 8] = [0x60, 0x01, 0x61, 0x02, 0x62, 0x03, 0x12, 0x06];
+
+    // A machine running a program, and a debugger reading a script
+    fn debug(rom: &[u8], script: &str) -> (Machine, Debugger) {
+        let mut machine = Machine::new(Quirks::vip(), Font::Chip8).unwrap();
+        machine.load(rom).unwrap();
+        let debugger = Debugger::with_input(Box::new(Cursor::new(script.to_string())));
+        (machine, debugger)
+    }
+
+    #[test]
+    fn breakpoints_pause_and_continue_resumes() {
+        let (mut machine, mut debugger) = debug(&PROGRAM, "b 204\nc\nc\n");
+        assert_eq!(debugger.prompt(&mut machine), Resume::Continue);
+        assert_eq!(debugger.run_frame(&mut machine), Ok(None));
+        assert_eq!(machine.cpu().pc(), 0x204);
+        assert_eq!(machine.cpu().registers()[..3], [1, 2, 0]);
+
+        // Resuming executes the instruction under the breakpoint
+        assert_eq!(debugger.prompt(&mut machine), Resume::Continue);
+        assert_eq!(
+            debugger.run_frame(&mut machine),
+            Ok(Some(StepOutcome::Executed))
+        );
+        assert_eq!(machine.cpu().registers()[..3], [1, 2, 3]);
+    }
+
+    #[test]
+    fn breakpoints_on_labels() {
+        let (mut machine, mut debugger) = debug(&PROGRAM, "b done\nc\n");
+        debugger.set_labels(BTreeMap::from([("done".to_string(), 0x206)]));
+        assert_eq!(debugger.prompt(&mut machine), Resume::Continue);
+        assert_eq!(debugger.run_frame(&mut machine), Ok(None));
+        assert_eq!(machine.cpu().pc(), 0x206);
+    }
+
+    #[test]
+    fn step_counts_instructions() {
+        let (mut machine, mut debugger) = debug(&PROGRAM, "s 2\nq\n");
+        assert_eq!(debugger.prompt(&mut machine), Resume::Quit);
+        assert_eq!(machine.cpu().pc(), 0x204);
+
+        // An empty line repeats the step
+        let (mut machine, mut debugger) = debug(&PROGRAM, "s\n\n\nq\n");
+        assert_eq!(debugger.prompt(&mut machine), Resume::Quit);
+        assert_eq!(machine.cpu().pc(), 0x206);
+
+        // Stepping stops on a breakpoint
+        let (mut machine, mut debugger) = debug(&PROGRAM, "b 204\ns 10\nq\n");
+        assert_eq!(debugger.prompt(&mut machine), Resume::Quit);
+        assert_eq!(machine.cpu().pc(), 0x204);
+    }
+
+    #[test]
+    fn continue_for_frames() {
+        let (mut machine, mut debugger) = debug(&PROGRAM, "c 2\n");
+        assert_eq!(debugger.prompt(&mut machine), Resume::Continue);
+        assert!(debugger.run_frame(&mut machine).unwrap().is_some());
+        assert!(debugger.run_frame(&mut machine).unwrap().is_some());
+        assert_eq!(debugger.run_frame(&mut machine), Ok(None));
+    }
+
+    #[test]
+    fn finish_runs_until_the_return() {
+        // 200: call 204, 202: loop, 204: v0 := 5, 206: return
+        let rom = [0x22, 0x04, 0x12, 0x02, 0x60, 0x05, 0x00, 0xEE];
+        let (mut machine, mut debugger) = debug(&rom, "f\ns\nf\n");
+        assert_eq!(debugger.prompt(&mut machine), Resume::Continue);
+        assert_eq!(debugger.run_frame(&mut machine), Ok(None));
+        assert_eq!(machine.cpu().pc(), 0x202);
+        assert_eq!(machine.cpu().registers()[0], 5);
+    }
+
+    #[test]
+    fn registers_and_memory_edits() {
+        let script = "set v3 42\nset I 300\nset pc 204\nset dt 10\npoke 300 aa #bb 0xcc\nq\n";
+        let (mut machine, mut debugger) = debug(&PROGRAM, script);
+        assert_eq!(debugger.prompt(&mut machine), Resume::Quit);
+        let cpu = machine.cpu();
+        assert_eq!(cpu.registers()[3], 0x42);
+        assert_eq!(cpu.i_register(), 0x300);
+        assert_eq!(cpu.pc(), 0x204);
+        assert_eq!(cpu.delay_timer(), 0x10);
+        assert_eq!(cpu.ram()[0x300..0x303], [0xAA, 0xBB, 0xCC]);
+    }
+
+    #[test]
+    fn bad_input_changes_nothing() {
+        let script = "set v3 100\nset vg 1\nset v3\nset i 10000\npoke 300 1ff\npoke 10000 1\n\
+                      poke\nb zz\nd 204\nkey 10\nkey 1 left\ns x\nfoo\nq\n";
+        let (mut machine, mut debugger) = debug(&PROGRAM, script);
+        assert_eq!(debugger.prompt(&mut machine), Resume::Quit);
+        let cpu = machine.cpu();
+        assert_eq!(cpu.registers()[3], 0);
+        assert_eq!(cpu.i_register(), 0);
+        assert_eq!(cpu.pc(), 0x200);
+        assert_eq!(cpu.ram()[0x300], 0);
+    }
+
+    #[test]
+    fn end_of_input_quits() {
+        let (mut machine, mut debugger) = debug(&PROGRAM, "r\nx 200 4\nl\nlabels\n");
+        assert_eq!(debugger.prompt(&mut machine), Resume::Quit);
+    }
+}
@@ -0,0 +1,250 @@
+// Importing useful modules
+use super::instruction::{Instruction, Variant};
+use std::fmt;
+
+// How the mnemonics are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Syntax {
+    // Cowgod's Chip-8 technical reference: LD V0, #05
+    Cowgod,
+
+    // The Octo assembly language: v0 := 0x05
+    Octo,
+}
+
+// Syntax methods
+impl Syntax {
+    // Get a syntax by its name
+    pub fn from_name(name: &str) -> Option<Syntax> {
+        match name {
+            "cowgod" => Some(Syntax::Cowgod),
+            "octo" => Some(Syntax::Octo),
+            _ => None,
+        }
+    }
+}
+
+// A line of a disassembly: an instruction, or data when the bytes aren't one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    // Adress of the first byte
+    pub adress: u16,
+
+    // The raw bytes, 2 for most instructions, 4 for F000 NNNN, 1 for a lone byte at the end
+    pub bytes: Vec<u8>,
+
+    // The mnemonic
+    pub text: String,
+}
+
+// Line methods
+impl Line {
+    // The raw bytes in hexadecimal, grouped by words
+    pub fn words(&self) -> String {
+        let words: Vec<String> = self
+            .bytes
+            .chunks(2)
+            .map(|word| word.iter().map(|b| format!("{:02X}", b)).collect())
+            .collect();
+        words.join(" ")
+    }
+
+    // The line as source code: the mnemonic, then the adress and raw words in a comment,
+    // so it can be assembled back into the same bytes
+    pub fn source(&self, syntax: Syntax) -> String {
+        let comment = match syntax {
+            Syntax::Cowgod => ';',
+            Syntax::Octo => '#',
+        };
+        format!(
+            "    {:<24}{} {:04X}  {}",
+            self.text,
+            comment,
+            self.adress,
+            self.words()
+        )
+    }
+}
+
+// Lines are printed as the adress, the raw words and the mnemonic
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04X}  {:<9}  {}", self.adress, self.words(), self.text)
+    }
+}
+
+// Disassemble the bytes of a program loaded at an adress (0x200 for roms), two bytes at a time
+pub fn disassemble_rom(rom: &[u8], origin: u16, syntax: Syntax, variant: Variant) -> Vec<Line> {
+    let byte = |i: usize| rom.get(i).copied().unwrap_or(0) as u16;
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    while offset < rom.len() {
+        let opcode = (byte(offset) << 8) | byte(offset + 1);
+        let next = (byte(offset + 2) << 8) | byte(offset + 3);
+
+        // A lone byte at the end, or a long I load cut short, are data
+        let instruction = Instruction::decode(opcode, next, variant)
+            .filter(|instruction| offset + instruction.size() as usize <= rom.len());
+        let size = instruction.map_or(2, |instruction| instruction.size() as usize);
+        let bytes = rom[offset..(offset + size).min(rom.len())].to_vec();
+        let text = match instruction {
+            Some(instruction) => mnemonic(&instruction, syntax),
+            None => data(&bytes, syntax),
+        };
+        lines.push(Line {
+            adress: origin.wrapping_add(offset as u16),
+            bytes,
+            text,
+        });
+        offset += size;
+    }
+    lines
+}
+
+// Mnemonic of a single instruction, next is the word following the opcode, only used by F000 NNNN.
+// Words which aren't instructions of the variant are shown as data
+pub fn disassemble(opcode: u16, next: u16, syntax: Syntax, variant: Variant) -> String {
+    match Instruction::decode(opcode, next, variant) {
+        Some(instruction) => mnemonic(&instruction, syntax),
+        None => data(&opcode.to_be_bytes(), syntax),
+    }
+}
+
+// Mnemonic of an instruction in a syntax
+pub fn mnemonic(instruction: &Instruction, syntax: Syntax) -> String {
+    match syntax {
+        Syntax::Cowgod => cowgod(instruction),
+        Syntax::Octo => octo(instruction),
+    }
+}
+
+// Bytes which aren't an instruction: a word (DW) or a byte (DB) in Cowgod syntax,
+// plain numbers in Octo
+fn data(bytes: &[u8], syntax: Syntax) -> String {
+    match (syntax, bytes) {
+        (Syntax::Cowgod, [high, low]) => format!("DW #{:02X}{:02X}", high, low),
+        (Syntax::Cowgod, _) => {
+            let bytes: Vec<String> = bytes.iter().map(|b| format!("#{:02X}", b)).collect();
+            format!("DB {}", bytes.join(", "))
+        }
+        (Syntax::Octo, _) => {
+            let bytes: Vec<String> = bytes.iter().map(|b| format!("0x{:02X}", b)).collect();
+            bytes.join(" ")
+        }
+    }
+}
+
+// Cowgod syntax, with the usual extensions for SUPER-CHIP and XO-CHIP
+fn cowgod(instruction: &Instruction) -> String {
+    match *instruction {
+        Instruction::Cls => "CLS".to_string(),
+        Instruction::Ret => "RET".to_string(),
+        Instruction::Sys(nnn) => format!("SYS #{:03X}", nnn),
+        Instruction::Jmp(nnn) => format!("JP #{:03X}", nnn),
+        Instruction::Call(nnn) => format!("CALL #{:03X}", nnn),
+        Instruction::SeVx(x, kk) => format!("SE V{:X}, #{:02X}", x, kk),
+        Instruction::SneVx(x, kk) => format!("SNE V{:X}, #{:02X}", x, kk),
+        Instruction::SeVxVy(x, y) => format!("SE V{:X}, V{:X}", x, y),
+        Instruction::SneVxVy(x, y) => format!("SNE V{:X}, V{:X}", x, y),
+        Instruction::LdVx(x, kk) => format!("LD V{:X}, #{:02X}", x, kk),
+        Instruction::AddVx(x, kk) => format!("ADD V{:X}, #{:02X}", x, kk),
+        Instruction::LdVxVy(x, y) => format!("LD V{:X}, V{:X}", x, y),
+        Instruction::OrVxVy(x, y) => format!("OR V{:X}, V{:X}", x, y),
+        Instruction::AndVxVy(x, y) => format!("AND V{:X}, V{:X}", x, y),
+        Instruction::XorVxVy(x, y) => format!("XOR V{:X}, V{:X}", x, y),
+        Instruction::AddVxVy(x, y) => format!("ADD V{:X}, V{:X}", x, y),
+        Instruction::SubVxVy(x, y) => format!("SUB V{:X}, V{:X}", x, y),
+        Instruction::ShrVx(x, y) => format!("SHR V{:X}, V{:X}", x, y),
+        Instruction::SubnVxVy(x, y) => format!("SUBN V{:X}, V{:X}", x, y),
+        Instruction::ShlVx(x, y) => format!("SHL V{:X}, V{:X}", x, y),
+        Instruction::LdI(nnn) => format!("LD I, #{:03X}", nnn),
+        Instruction::JpV0(nnn) => format!("JP V0, #{:03X}", nnn),
+        Instruction::RndVx(x, kk) => format!("RND V{:X}, #{:02X}", x, kk),
+        Instruction::DrwVxVy(x, y, n) => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        Instruction::SkpVx(x) => format!("SKP V{:X}", x),
+        Instruction::SknpVx(x) => format!("SKNP V{:X}", x),
+        Instruction::LdVxDt(x) => format!("LD V{:X}, DT", x),
+        Instruction::LdVxK(x) => format!("LD V{:X}, K", x),
+        Instruction::LdDtVx(x) => format!("LD DT, V{:X}", x),
+        Instruction::LdStVx(x) => format!("LD ST, V{:X}", x),
+        Instruction::AddIVx(x) => format!("ADD I, V{:X}", x),
+        Instruction::LdFVx(x) => format!("LD F, V{:X}", x),
+        Instruction::LdBVx(x) => format!("LD B, V{:X}", x),
+        Instruction::LdIVx(x) => format!("LD [I], V{:X}", x),
+        Instruction::LdVxI(x) => format!("LD V{:X}, [I]", x),
+        Instruction::ScrollDown(n) => format!("SCD {}", n),
+        Instruction::ScrollRight => "SCR".to_string(),
+        Instruction::ScrollLeft => "SCL".to_string(),
+        Instruction::Exit => "EXIT".to_string(),
+        Instruction::Lores => "LOW".to_string(),
+        Instruction::Hires => "HIGH".to_string(),
+        Instruction::LdHfVx(x) => format!("LD HF, V{:X}", x),
+        Instruction::LdRVx(x) => format!("LD R, V{:X}", x),
+        Instruction::LdVxR(x) => format!("LD V{:X}, R", x),
+        Instruction::ScrollUp(n) => format!("SCU {}", n),
+        Instruction::LdIVxVy(x, y) => format!("LD [I], V{:X}-V{:X}", x, y),
+        Instruction::LdVxVyI(x, y) => format!("LD V{:X}-V{:X}, [I]", x, y),
+        Instruction::LdILong(nnnn) => format!("LD I, LONG #{:04X}", nnnn),
+        Instruction::Plane(n) => format!("PLANE {}", n),
+        Instruction::LdAudioI => "AUDIO".to_string(),
+        Instruction::LdPitchVx(x) => format!("LD PITCH, V{:X}", x),
+    }
+}
+
+// Octo syntax. Skips are written as the condition of the following instruction:
+// 3XKK skips when equal, so it runs the next instruction if vX != KK
+fn octo(instruction: &Instruction) -> String {
+    match *instruction {
+        Instruction::Cls => "clear".to_string(),
+        Instruction::Ret => "return".to_string(),
+        Instruction::Sys(nnn) => data(&nnn.to_be_bytes(), Syntax::Octo),
+        Instruction::Jmp(nnn) => format!("jump 0x{:03X}", nnn),
+        Instruction::Call(nnn) => format!(":call 0x{:03X}", nnn),
+        Instruction::SeVx(x, kk) => format!("if v{:x} != 0x{:02X} then", x, kk),
+        Instruction::SneVx(x, kk) => format!("if v{:x} == 0x{:02X} then", x, kk),
+        Instruction::SeVxVy(x, y) => format!("if v{:x} != v{:x} then", x, y),
+        Instruction::SneVxVy(x, y) => format!("if v{:x} == v{:x} then", x, y),
+        Instruction::LdVx(x, kk) => format!("v{:x} := 0x{:02X}", x, kk),
+        Instruction::AddVx(x, kk) => format!("v{:x} += 0x{:02X}", x, kk),
+        Instruction::LdVxVy(x, y) => format!("v{:x} := v{:x}", x, y),
+        Instruction::OrVxVy(x, y) => format!("v{:x} |= v{:x}", x, y),
+        Instruction::AndVxVy(x, y) => format!("v{:x} &= v{:x}", x, y),
+        Instruction::XorVxVy(x, y) => format!("v{:x} ^= v{:x}", x, y),
+        Instruction::AddVxVy(x, y) => format!("v{:x} += v{:x}", x, y),
+        Instruction::SubVxVy(x, y) => format!("v{:x} -= v{:x}", x, y),
+        Instruction::ShrVx(x, y) => format!("v{:x} >>= v{:x}", x, y),
+        Instruction::SubnVxVy(x, y) => format!("v{:x} =- v{:x}", x, y),
+        Instruction::ShlVx(x, y) => format!("v{:x} <<= v{:x}", x, y),
+        Instruction::LdI(nnn) => format!("i := 0x{:03X}", nnn),
+        Instruction::JpV0(nnn) => format!("jump0 0x{:03X}", nnn),
+        Instruction::RndVx(x, kk) => format!("v{:x} := random 0x{:02X}", x, kk),
+        Instruction::DrwVxVy(x, y, n) => format!("sprite v{:x} v{:x} {}", x, y, n),
+        Instruction::SkpVx(x) => format!("if v{:x} -key then", x),
+        Instruction::SknpVx(x) => format!("if v{:x} key then", x),
+        Instruction::LdVxDt(x) => format!("v{:x} := delay", x),
+        Instruction::LdVxK(x) => format!("v{:x} := key", x),
+        Instruction::LdDtVx(x) => format!("delay := v{:x}", x),
+        Instruction::LdStVx(x) => format!("buzzer := v{:x}", x),
+        Instruction::AddIVx(x) => format!("i += v{:x}", x),
+        Instruction::LdFVx(x) => format!("i := hex v{:x}", x),
+        Instruction::LdBVx(x) => format!("bcd v{:x}", x),
+        Instruction::LdIVx(x) => format!("save v{:x}", x),
+        Instruction::LdVxI(x) => format!("load v{:x}", x),
+        Instruction::ScrollDown(n) => format!("scroll-down {}", n),
+        Instruction::ScrollRight => "scroll-right".to_string(),
+        Instruction::ScrollLeft => "scroll-left".to_string(),
+        Instruction::Exit => "exit".to_string(),
+        Instruction::Lores => "lores".to_string(),
+        Instruction::Hires => "hires".to_string(),
+        Instruction::LdHfVx(x) => format!("i := bighex v{:x}", x),
+        Instruction::LdRVx(x) => format!("saveflags v{:x}", x),
+        Instruction::LdVxR(x) => format!("loadflags v{:x}", x),
+        Instruction::ScrollUp(n) => format!("scroll-up {}", n),
+        Instruction::LdIVxVy(x, y) => format!("save v{:x} - v{:x}", x, y),
+        Instruction::LdVxVyI(x, y) => format!("load v{:x} - v{:x}", x, y),
+        Instruction::LdILong(nnnn) => format!("i := long 0x{:04X}", nnnn),
+        Instruction::Plane(n) => format!("plane {}", n),
+        Instruction::LdAudioI => "audio".to_string(),
+        Instruction::LdPitchVx(x) => format!("pitch := v{:x}", x),
+    }
+}
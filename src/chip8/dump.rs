@@ -0,0 +1,93 @@
+// Importing useful modules
+use super::framebuffer::Framebuffer;
+use std::io;
+
+// Characters of the pixels by the XO-CHIP planes they are lit on: none, first, second, both
+const ASCII_PIXELS: [char; 4] = ['.', '#', 'o', '@'];
+
+// Colors of the pixels in PNG images (RGB), the same as in the window
+const PNG_PALETTE: [u8; 12] = [0, 0, 0, 255, 255, 255, 255, 102, 0, 102, 33, 0];
+
+// Formats of the screen dumps
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DumpFormat {
+    // Text, one character per pixel
+    Ascii,
+
+    // Binary portable bitmap, lit pixels are black
+    Pbm,
+
+    // PNG image with the window colors
+    Png,
+}
+
+impl DumpFormat {
+    // Format matching the extension of a file, ASCII art for anything else
+    pub fn from_path(path: &str) -> DumpFormat {
+        let path = path.to_lowercase();
+        if path.ends_with(".png") {
+            DumpFormat::Png
+        } else if path.ends_with(".pbm") {
+            DumpFormat::Pbm
+        } else {
+            DumpFormat::Ascii
+        }
+    }
+}
+
+// Dump the screen in a given format
+pub fn dump(screen: &Framebuffer, format: DumpFormat) -> io::Result<Vec<u8>> {
+    match format {
+        DumpFormat::Ascii => Ok(to_ascii(screen).into_bytes()),
+        DumpFormat::Pbm => Ok(to_pbm(screen)),
+        DumpFormat::Png => to_png(screen),
+    }
+}
+
+// Screen as ASCII art, one line per row
+pub fn to_ascii(screen: &Framebuffer) -> String {
+    let (width, height) = (screen.width(), screen.height());
+    let mut text = String::with_capacity((width + 1) * height);
+    for y in 0..height {
+        for x in 0..width {
+            text.push(ASCII_PIXELS[screen.pixel(x, y) as usize]);
+        }
+        text.push('\n');
+    }
+    text
+}
+
+// Screen as a binary PBM (P4) image, lit pixels on any plane are black
+pub fn to_pbm(screen: &Framebuffer) -> Vec<u8> {
+    let (width, height) = (screen.width(), screen.height());
+    let mut bytes = format!("P4\n{} {}\n", width, height).into_bytes();
+    let row_bytes = width.div_ceil(8);
+    for y in 0..height {
+        // Rows are already packed most significant bit first, padded on the right to whole bytes
+        let lit = screen.rows(0)[y] | screen.rows(1)[y];
+        let padded = lit << (row_bytes * 8 - width);
+        bytes.extend_from_slice(&padded.to_be_bytes()[16 - row_bytes..]);
+    }
+    bytes
+}
+
+// Screen as a PNG image, using one palette entry per combination of planes
+pub fn to_png(screen: &Framebuffer) -> io::Result<Vec<u8>> {
+    let (width, height) = (screen.width(), screen.height());
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            pixels.push(screen.pixel(x, y));
+        }
+    }
+
+    let mut bytes = Vec::new();
+    let mut encoder = png::Encoder::new(&mut bytes, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_palette(&PNG_PALETTE[..]);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&pixels)?;
+    writer.finish()?;
+    Ok(bytes)
+}
@@ -0,0 +1,52 @@
+// Importing useful modules
+use std::{error::Error, fmt};
+
+// Errors stopping the CPU, each one carries the adress and the opcode of the faulty instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CpuError {
+    // The opcode is not part of the instruction set
+    UnknownOpcode { pc: u16, opcode: u16 },
+
+    // A subroutine call went past the top of the stack
+    StackOverflow { pc: u16, opcode: u16 },
+
+    // A return happened with an empty stack
+    StackUnderflow { pc: u16, opcode: u16 },
+
+    // The PC left the memory, the opcode is the last one executed before
+    PcOutOfBounds { pc: u16, opcode: u16 },
+
+    // An instruction read or wrote outside of the memory
+    MemoryOutOfBounds { pc: u16, opcode: u16, adress: usize },
+}
+
+// Human readable errors
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CpuError::UnknownOpcode { pc, opcode } => {
+                write!(f, "Unknown opcode {:#06x} at PC={:#05x}", opcode, pc)
+            }
+            CpuError::StackOverflow { pc, opcode } => {
+                write!(f, "Stack overflow by {:#06x} at PC={:#05x}", opcode, pc)
+            }
+            CpuError::StackUnderflow { pc, opcode } => {
+                write!(f, "Stack underflow by {:#06x} at PC={:#05x}", opcode, pc)
+            }
+            CpuError::PcOutOfBounds { pc, opcode } => {
+                write!(
+                    f,
+                    "PC={:#05x} out of memory after opcode {:#06x}",
+                    pc, opcode
+                )
+            }
+            CpuError::MemoryOutOfBounds { pc, opcode, adress } => write!(
+                f,
+                "Memory access at {:#06x} out of memory by {:#06x} at PC={:#05x}",
+                adress, opcode, pc
+            ),
+        }
+    }
+}
+
+impl Error for CpuError {}
@@ -0,0 +1,160 @@
+// Size of the screen in low resolution
+pub const LORES_WIDTH: usize = 64;
+pub const LORES_HEIGHT: usize = 32;
+
+// Size of the screen in high resolution (SCHIP/XO-CHIP)
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
+
+// Number of bitplanes (XO-CHIP), plain chip8 programs only draw on the first one
+pub const PLANES: usize = 2;
+
+// The screen, one bit per pixel and per plane. Each row of a plane is packed in a u128,
+// the leftmost pixel being the most significant bit of the row: bit width - 1 - x holds pixel x.
+// The value of a pixel is the mask of the planes it is lit on, turning it into a color is up to the renderer
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Framebuffer {
+    width: usize,
+    height: usize,
+    rows: [Vec<u128>; PLANES],
+}
+
+// Framebuffer methods
+impl Framebuffer {
+    // A blank screen, at most 128 pixels wide
+    pub fn new(width: usize, height: usize) -> Framebuffer {
+        assert!(width <= 128, "A row holds at most 128 pixels");
+        Framebuffer {
+            width,
+            height,
+            rows: [vec![0; height], vec![0; height]],
+        }
+    }
+
+    // A blank 64x32 screen
+    pub fn lores() -> Framebuffer {
+        Framebuffer::new(LORES_WIDTH, LORES_HEIGHT)
+    }
+
+    // A blank 128x64 screen
+    pub fn hires() -> Framebuffer {
+        Framebuffer::new(HIRES_WIDTH, HIRES_HEIGHT)
+    }
+
+    // Width in pixels
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    // Height in pixels
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    // If this is a high resolution screen
+    pub fn is_hires(&self) -> bool {
+        self.width > LORES_WIDTH
+    }
+
+    // A row of a plane, pixel x is bit width - 1 - x
+    pub fn row(&self, plane: usize, y: usize) -> u128 {
+        self.rows[plane][y]
+    }
+
+    // A row of a plane of a low resolution screen, None for rows wider than 64 pixels
+    pub fn row_u64(&self, plane: usize, y: usize) -> Option<u64> {
+        u64::try_from(self.rows[plane][y]).ok()
+    }
+
+    // Replace a row of a plane, the bits past the right edge are dropped
+    pub fn set_row(&mut self, plane: usize, y: usize, row: u128) {
+        self.rows[plane][y] = row & self.mask();
+    }
+
+    // All the rows of a plane, from top to bottom
+    pub fn rows(&self, plane: usize) -> &[u128] {
+        &self.rows[plane]
+    }
+
+    // The mask of the planes a pixel is lit on (0 to 3)
+    pub fn pixel(&self, x: usize, y: usize) -> u8 {
+        let bit = self.bit(x);
+        let mut value = 0;
+        for (plane, rows) in self.rows.iter().enumerate() {
+            if rows[y] & bit != 0 {
+                value |= 1 << plane;
+            }
+        }
+        value
+    }
+
+    // Flip a pixel on the given planes, returns if a lit pixel got turned off
+    pub fn toggle(&mut self, planes: u8, x: usize, y: usize) -> bool {
+        let bit = self.bit(x);
+        let mut collision = false;
+        for (plane, rows) in self.rows.iter_mut().enumerate() {
+            if planes & (1 << plane) != 0 {
+                collision |= rows[y] & bit != 0;
+                rows[y] ^= bit;
+            }
+        }
+        collision
+    }
+
+    // Turn off all the pixels of the given planes
+    pub fn clear(&mut self, planes: u8) {
+        for (plane, rows) in self.rows.iter_mut().enumerate() {
+            if planes & (1 << plane) != 0 {
+                rows.fill(0);
+            }
+        }
+    }
+
+    // Scroll the given planes by dx pixels right and dy pixels down, pixels leaving the screen are lost
+    pub fn scroll(&mut self, planes: u8, dx: isize, dy: isize) {
+        let mask = self.mask();
+        let height = self.height;
+        for (plane, rows) in self.rows.iter_mut().enumerate() {
+            if planes & (1 << plane) == 0 {
+                continue;
+            }
+
+            // Vertically, whole rows move
+            let shift = dy.unsigned_abs().min(height);
+            if dy > 0 {
+                rows.rotate_right(shift);
+                rows[..shift].fill(0);
+            } else if dy < 0 {
+                rows.rotate_left(shift);
+                rows[height - shift..].fill(0);
+            }
+
+            // Horizontally, moving right means towards the least significant bits
+            let shift = dx.unsigned_abs() as u32;
+            for row in rows.iter_mut() {
+                *row = if shift >= 128 {
+                    0
+                } else if dx > 0 {
+                    *row >> shift
+                } else {
+                    (*row << shift) & mask
+                };
+            }
+        }
+    }
+
+    // The bit of pixel x in a row
+    fn bit(&self, x: usize) -> u128 {
+        1 << (self.width - 1 - x)
+    }
+
+    // The bits of a row which are on the screen
+    fn mask(&self) -> u128 {
+        if self.width == 128 {
+            u128::MAX
+        } else {
+            (1 << self.width) - 1
+        }
+    }
+}
@@ -0,0 +1,184 @@
+// Importing useful modules
+use super::cpu::StepOutcome;
+use super::debugger::{Debugger, Resume};
+use super::error::CpuError;
+use super::framebuffer::Framebuffer;
+use super::machine::Machine;
+use super::rewind::RewindBuffer;
+use super::savestate::SaveSlots;
+use super::scheduler::Scheduler;
+use std::time::Duration;
+
+// Something happening on the input side of a frontend
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputEvent {
+    // A key of the keypad (0x0-0xF) is pressed or released
+    Key { key: u8, pressed: bool },
+
+    // Save the machine in a numbered slot
+    SaveState(u8),
+
+    // Restore the machine from a numbered slot
+    LoadState(u8),
+
+    // The rewind hotkey is held or released
+    Rewind(bool),
+
+    // Pause the machine in the debugger, if one is attached
+    Break,
+
+    // The user wants to quit
+    Quit,
+}
+
+// Where the screen gets displayed
+pub trait DisplaySink {
+    // Show the screen, frontends wanting to keep it copy it with clone_from
+    fn present(&mut self, screen: &Framebuffer);
+}
+
+// Where the keypad input comes from
+pub trait InputSource {
+    // Events since the last call, waiting for them about as long as the timeout
+    fn poll(&mut self, timeout: Duration) -> Vec<InputEvent>;
+}
+
+// Where the buzzer sounds
+pub trait AudioSink {
+    // Start or stop the buzzer, it sounds while the sound timer is non-zero. Called once per frame
+    fn set_buzzer(&mut self, active: bool);
+
+    // The XO-CHIP pattern played by the buzzer instead of the tone, and its pitch. Called before set_buzzer
+    fn set_pattern(&mut self, _pattern: Option<&[u8; 16]>, _pitch: u8) {}
+
+    // Silence the buzzer without advancing the time, while the emulation is paused or rewinding
+    fn pause(&mut self) {}
+}
+
+// Audio sink for frontends without sound
+pub struct NullAudio;
+
+impl AudioSink for NullAudio {
+    fn set_buzzer(&mut self, _active: bool) {}
+}
+
+// Hand the audio state of the machine to a sink, once per frame
+pub fn update_audio(machine: &Machine, audio: &mut dyn AudioSink) {
+    audio.set_pattern(machine.audio_pattern(), machine.pitch());
+    audio.set_buzzer(machine.sound_active());
+}
+
+// The emulation loop, until the user quits or the program exits. The scheduler decides how many
+// 60 Hz frames are due, the screen is presented once after running them. Each frame is recorded
+// in the rewind buffer, played backwards while the rewind hotkey is held.
+// With a debugger, the machine starts paused at its prompt and pauses again on breakpoints, the
+// break hotkey, or errors.
+// Display and input usually share a window, so they are implemented by the same frontend
+pub fn run<F>(
+    machine: &mut Machine,
+    frontend: &mut F,
+    audio: &mut dyn AudioSink,
+    slots: &SaveSlots,
+    rewind: &mut RewindBuffer,
+    mut debugger: Option<&mut Debugger>,
+) -> Result<(), CpuError>
+where
+    F: DisplaySink + InputSource,
+{
+    let mut scheduler = Scheduler::default();
+    let mut rewinding = false;
+    let mut paused = debugger.is_some();
+    rewind.push(&machine.state());
+    loop {
+        // The debugger prompt, the screen shows where the machine is paused
+        if paused {
+            if let Some(debugger) = debugger.as_deref_mut() {
+                frontend.present(machine.framebuffer());
+                audio.pause();
+                if debugger.prompt(machine) == Resume::Quit {
+                    return Ok(());
+                }
+                scheduler.restart();
+            }
+            paused = false;
+        }
+
+        // Keypad input, waiting for the next frame
+        for event in frontend.poll(scheduler.until_next_frame()) {
+            match event {
+                InputEvent::Key { key, pressed } => machine.set_key(key, pressed),
+                InputEvent::SaveState(slot) => match slots.save(machine, slot) {
+                    Ok(()) => eprintln!("Saved state to {}", slots.path(slot)),
+                    Err(e) => eprintln!("{}", e),
+                },
+                InputEvent::LoadState(slot) => match slots.load(machine, slot) {
+                    Ok(()) => {
+                        eprintln!("Loaded state from {}", slots.path(slot));
+                        frontend.present(machine.framebuffer());
+                        rewind.clear();
+                        rewind.push(&machine.state());
+                    }
+                    Err(e) => eprintln!("{}", e),
+                },
+                InputEvent::Rewind(held) => rewinding = held,
+                InputEvent::Break => paused = debugger.is_some(),
+                InputEvent::Quit => return Ok(()),
+            }
+        }
+
+        // Emulating the frames due
+        let frames = scheduler.frames_due();
+        if frames == 0 {
+            continue;
+        }
+
+        // Going back one recorded frame per frame, silently, until the oldest one. The frames
+        // undone aren't recorded by the audio sink
+        if rewinding {
+            audio.pause();
+            for _ in 0..frames {
+                if let Some(state) = rewind.pop() {
+                    machine
+                        .load_state(state)
+                        .expect("Rewind snapshots come from the machine");
+                }
+            }
+            frontend.present(machine.framebuffer());
+            continue;
+        }
+
+        let mut outcome = StepOutcome::Executed;
+        for _ in 0..frames {
+            let frame = match debugger.as_deref_mut() {
+                Some(debugger) => debugger.run_frame(machine),
+                None => machine.run_frame().map(Some),
+            };
+            outcome = match frame {
+                Ok(Some(outcome)) => outcome,
+                Ok(None) => {
+                    paused = true;
+                    break;
+                }
+                // Errors are reported at the debugger prompt, where the machine can be inspected
+                Err(error) if debugger.is_some() => {
+                    println!("Emulation stopped: {}", error);
+                    paused = true;
+                    break;
+                }
+                Err(error) => return Err(error),
+            };
+            update_audio(machine, audio);
+            rewind.push(&machine.state());
+            if outcome == StepOutcome::Exit {
+                break;
+            }
+        }
+
+        // Output
+        frontend.present(machine.framebuffer());
+
+        if outcome == StepOutcome::Exit {
+            return Ok(());
+        }
+    }
+}
@@ -4,23 +4,77 @@ extern crate graphics;
 extern crate opengl_graphics;
 extern crate piston;
 
+use super::framebuffer::Framebuffer;
+use super::frontend::{DisplaySink, InputEvent, InputSource};
+use super::keymap::Keymap;
+use super::scheduler::FRAME_RATE;
 use glutin_window::GlutinWindow as Window;
 use opengl_graphics::{GlGraphics, OpenGL};
-use piston::input::RenderArgs;
+use piston::event_loop::{EventLoop, EventSettings, Events};
+use piston::input::{
+    Button, ButtonEvent, ButtonState, Key, PressEvent, ReleaseEvent, RenderArgs, RenderEvent,
+    UpdateEvent,
+};
 use piston::window::WindowSettings;
+use std::time::{Duration, Instant};
 
-// The GPU of the chip8
+// Colors of the pixels by the XO-CHIP planes they are lit on: none, first, second, both
+const PALETTE: [[f32; 4]; 4] = [
+    [0.0, 0.0, 0.0, 1.0],
+    [1.0, 1.0, 1.0, 1.0],
+    [1.0, 0.4, 0.0, 1.0],
+    [0.4, 0.13, 0.0, 1.0],
+];
+
+// Scancodes of the digits row, 1 to 9 then 0, the same on Linux and Windows
+const DIGIT_SCANCODES: std::ops::RangeInclusive<i32> = 2..=11;
+
+// Name of a keyboard key in the keymaps: the character it types (piston key codes are ASCII
+// for printable keys), or the name of a few special keys. Keys piston doesn't know, like the
+// digits row of AZERTY keyboards (& é " '), are named by the digit at their position
+fn host_key(key: Key, scancode: Option<i32>) -> Option<String> {
+    match key {
+        Key::Space => Some("space".to_string()),
+        Key::Return => Some("enter".to_string()),
+        Key::Tab => Some("tab".to_string()),
+        Key::Up => Some("up".to_string()),
+        Key::Down => Some("down".to_string()),
+        Key::Left => Some("left".to_string()),
+        Key::Right => Some("right".to_string()),
+        _ => u8::try_from(key.code())
+            .ok()
+            .filter(|code| code.is_ascii_graphic())
+            .map(|code| (code as char).to_ascii_lowercase().to_string())
+            .or_else(|| {
+                let scancode = scancode.filter(|code| DIGIT_SCANCODES.contains(code))?;
+                Some(((scancode - 1) % 10).to_string())
+            }),
+    }
+}
+
+// Save state slot of a function key, F1 to F9
+fn state_slot(key: Key) -> Option<u8> {
+    let code = key.code() - Key::F1.code();
+    (0..9).contains(&code).then(|| code as u8 + 1)
+}
+
+// The GPU of the chip8, a piston window displaying the screen and reading the keyboard
 pub struct Gpu {
-    size_factor: u8,
+    size_factor: u32,
     black: [f32; 4],
-    pub window: Window,
+    window: Window,
+    events: Events,
     gl: GlGraphics,
-    screen: Vec<Vec<[f32; 4]>>,
+    screen: Framebuffer,
+    keymap: Keymap,
+
+    // If a shift key is held, F1-F9 save states instead of loading them
+    shift: bool,
 }
 
 // GPU methods
 impl Gpu {
-    pub fn new(size_factor: u32) -> Gpu {
+    pub fn new(size_factor: u32, keymap: Keymap) -> Gpu {
         // Creating window
         let width: u32 = 64 * size_factor;
         let heigth: u32 = 32 * size_factor;
@@ -31,23 +85,31 @@ impl Gpu {
             .unwrap();
 
         // Creating new instance of a GPU
-        let _gpu = Gpu {
-            size_factor: 4,
+        Gpu {
+            size_factor,
             black: [0.0, 0.0, 0.0, 1.0],
             window: _window,
+            events: Events::new(
+                EventSettings::new()
+                    .ups(FRAME_RATE as u64)
+                    .max_fps(FRAME_RATE as u64),
+            ),
             gl: GlGraphics::new(OpenGL::V3_2),
-            screen: vec![vec![[0.5, 0.1, 0.71, 1.0]; 32]; 64],
-        };
-
-        return _gpu;
+            screen: Framebuffer::lores(),
+            keymap,
+            shift: false,
+        }
     }
 
     // Render screen buffer to window
-    pub fn render(&mut self, &args: &RenderArgs) {
+    fn render(&mut self, &args: &RenderArgs) {
         use graphics::*;
 
-        // A square here represents a pixel
-        let square = rectangle::square(0.0, 0.0, self.size_factor as f64);
+        // A square here represents a pixel, smaller in high resolution to fill the same window
+        let width = self.screen.width();
+        let height = self.screen.height();
+        let pixel_size = (64 * self.size_factor) as f64 / width as f64;
+        let square = rectangle::square(0.0, 0.0, pixel_size);
 
         // Rendering logic
         self.gl.draw(args.viewport(), |c, gl| {
@@ -55,19 +117,89 @@ impl Gpu {
             clear(self.black, gl);
 
             // Looping througth all pixel and render it
-            for i in 0..64 {
-                for j in 0..32 {
-                    let x = (i * self.size_factor) as f64;
-                    let y = (j * self.size_factor) as f64;
+            for i in 0..width {
+                for j in 0..height {
+                    let x = i as f64 * pixel_size;
+                    let y = j as f64 * pixel_size;
                     let transform = c.transform.trans(x, y);
-                    rectangle(self.screen[i as usize][j as usize], square, transform, gl);
+                    let color = PALETTE[self.screen.pixel(i, j) as usize];
+                    rectangle(color, square, transform, gl);
                 }
             }
         });
     }
+}
+
+// The window shows the latest screen buffer on each render event
+impl DisplaySink for Gpu {
+    fn present(&mut self, screen: &Framebuffer) {
+        self.screen.clone_from(screen);
+    }
+}
+
+// Handling the piston events until the timeout elapses. Piston blocks until its next event,
+// update events coming at 60 Hz make sure this returns in time
+impl InputSource for Gpu {
+    fn poll(&mut self, timeout: Duration) -> Vec<InputEvent> {
+        let mut input = Vec::new();
+        let deadline = Instant::now() + timeout;
+        while let Some(e) = self.events.next(&mut self.window) {
+            // Render graphics
+            if let Some(args) = e.render_args() {
+                self.render(&args);
+            }
+
+            // Save states hotkeys
+            if let Some(Button::Keyboard(key)) = e.press_args() {
+                if let Some(slot) = state_slot(key) {
+                    input.push(if self.shift {
+                        InputEvent::SaveState(slot)
+                    } else {
+                        InputEvent::LoadState(slot)
+                    });
+                }
+            }
+            if let Some(Button::Keyboard(Key::LShift | Key::RShift)) = e.press_args() {
+                self.shift = true;
+            }
+            if let Some(Button::Keyboard(Key::LShift | Key::RShift)) = e.release_args() {
+                self.shift = false;
+            }
+
+            // Rewind hotkey, held to play the game backwards
+            if let Some(Button::Keyboard(Key::Backspace)) = e.press_args() {
+                input.push(InputEvent::Rewind(true));
+            }
+            if let Some(Button::Keyboard(Key::Backspace)) = e.release_args() {
+                input.push(InputEvent::Rewind(false));
+            }
+
+            // Debugger hotkey, pausing the machine at the prompt
+            if let Some(Button::Keyboard(Key::F12)) = e.press_args() {
+                input.push(InputEvent::Break);
+            }
+
+            // Keypad input
+            if let Some(args) = e.button_args() {
+                if let Button::Keyboard(key) = args.button {
+                    let host = host_key(key, args.scancode);
+                    if let Some(key) = host.and_then(|host| self.keymap.key(&host)) {
+                        input.push(InputEvent::Key {
+                            key,
+                            pressed: args.state == ButtonState::Press,
+                        });
+                    }
+                }
+            }
+
+            // The next frame may be due
+            if e.update_args().is_some() || Instant::now() >= deadline {
+                return input;
+            }
+        }
 
-    // Update the current screen buffer of the GPU
-    pub fn update(&mut self, screen_buffer: Vec<Vec<[f32; 4]>>) {
-        self.screen = screen_buffer;
+        // The window has been closed
+        input.push(InputEvent::Quit);
+        input
     }
 }
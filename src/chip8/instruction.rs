@@ -0,0 +1,278 @@
+// Platforms, each one adding instructions to the previous one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Variant {
+    // The original instruction set
+    Chip8,
+
+    // SUPER-CHIP 1.1: scrolling, high resolution, big font and RPL flags
+    Schip,
+
+    // XO-CHIP: planes, audio patterns, registers ranges and long I loads
+    XoChip,
+}
+
+// Variant methods
+impl Variant {
+    // Get a variant by its name
+    pub fn from_name(name: &str) -> Option<Variant> {
+        match name {
+            "chip8" => Some(Variant::Chip8),
+            "schip" => Some(Variant::Schip),
+            "xochip" => Some(Variant::XoChip),
+            _ => None,
+        }
+    }
+}
+
+// An instruction decoded from its opcode, named after the CPU method executing it.
+// x and y are register indexes, the other fields the values encoded in the opcode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    // 00E0, 00EE, 0NNN (machine code routine, run as a jump)
+    Cls,
+    Ret,
+    Sys(u16),
+
+    // 1NNN, 2NNN
+    Jmp(u16),
+    Call(u16),
+
+    // 3XKK, 4XKK, 5XY0, 9XY0: skips
+    SeVx(u8, u8),
+    SneVx(u8, u8),
+    SeVxVy(u8, u8),
+    SneVxVy(u8, u8),
+
+    // 6XKK, 7XKK
+    LdVx(u8, u8),
+    AddVx(u8, u8),
+
+    // 8XY0 to 8XYE
+    LdVxVy(u8, u8),
+    OrVxVy(u8, u8),
+    AndVxVy(u8, u8),
+    XorVxVy(u8, u8),
+    AddVxVy(u8, u8),
+    SubVxVy(u8, u8),
+    ShrVx(u8, u8),
+    SubnVxVy(u8, u8),
+    ShlVx(u8, u8),
+
+    // ANNN, BNNN, CXKK, DXYN
+    LdI(u16),
+    JpV0(u16),
+    RndVx(u8, u8),
+    DrwVxVy(u8, u8, u8),
+
+    // EX9E, EXA1
+    SkpVx(u8),
+    SknpVx(u8),
+
+    // FX07, FX0A, FX15, FX18, FX1E, FX29, FX33, FX55, FX65
+    LdVxDt(u8),
+    LdVxK(u8),
+    LdDtVx(u8),
+    LdStVx(u8),
+    AddIVx(u8),
+    LdFVx(u8),
+    LdBVx(u8),
+    LdIVx(u8),
+    LdVxI(u8),
+
+    // SUPER-CHIP: 00CN, 00FB, 00FC, 00FD, 00FE, 00FF, FX30, FX75, FX85
+    ScrollDown(u8),
+    ScrollRight,
+    ScrollLeft,
+    Exit,
+    Lores,
+    Hires,
+    LdHfVx(u8),
+    LdRVx(u8),
+    LdVxR(u8),
+
+    // XO-CHIP: 00DN, 5XY2, 5XY3, F000 NNNN, FN01, F002, FX3A
+    ScrollUp(u8),
+    LdIVxVy(u8, u8),
+    LdVxVyI(u8, u8),
+    LdILong(u16),
+    Plane(u8),
+    LdAudioI,
+    LdPitchVx(u8),
+}
+
+// Instruction methods
+impl Instruction {
+    // Decode an opcode, next is the word following it, only used by F000 NNNN.
+    // None for the words which aren't instructions of the variant: these are data
+    pub fn decode(opcode: u16, next: u16, variant: Variant) -> Option<Instruction> {
+        let x = ((opcode & 0x0F00) >> 8) as u8;
+        let y = ((opcode & 0x00F0) >> 4) as u8;
+        let n = (opcode & 0x000F) as u8;
+        let kk = (opcode & 0x00FF) as u8;
+        let nnn = opcode & 0x0FFF;
+
+        let instruction = match opcode & 0xF000 {
+            0x0000 => match nnn {
+                0x00E0 => Instruction::Cls,
+                0x00EE => Instruction::Ret,
+                0x00FB => Instruction::ScrollRight,
+                0x00FC => Instruction::ScrollLeft,
+                0x00FD => Instruction::Exit,
+                0x00FE => Instruction::Lores,
+                0x00FF => Instruction::Hires,
+                op if op & 0x0FF0 == 0x00C0 => Instruction::ScrollDown(n),
+                op if op & 0x0FF0 == 0x00D0 => Instruction::ScrollUp(n),
+                _ => Instruction::Sys(nnn),
+            },
+            0x1000 => Instruction::Jmp(nnn),
+            0x2000 => Instruction::Call(nnn),
+            0x3000 => Instruction::SeVx(x, kk),
+            0x4000 => Instruction::SneVx(x, kk),
+            0x5000 => match n {
+                0x0 => Instruction::SeVxVy(x, y),
+                0x2 => Instruction::LdIVxVy(x, y),
+                0x3 => Instruction::LdVxVyI(x, y),
+                _ => return None,
+            },
+            0x6000 => Instruction::LdVx(x, kk),
+            0x7000 => Instruction::AddVx(x, kk),
+            0x8000 => match n {
+                0x0 => Instruction::LdVxVy(x, y),
+                0x1 => Instruction::OrVxVy(x, y),
+                0x2 => Instruction::AndVxVy(x, y),
+                0x3 => Instruction::XorVxVy(x, y),
+                0x4 => Instruction::AddVxVy(x, y),
+                0x5 => Instruction::SubVxVy(x, y),
+                0x6 => Instruction::ShrVx(x, y),
+                0x7 => Instruction::SubnVxVy(x, y),
+                0xE => Instruction::ShlVx(x, y),
+                _ => return None,
+            },
+            0x9000 if n == 0 => Instruction::SneVxVy(x, y),
+            0xA000 => Instruction::LdI(nnn),
+            0xB000 => Instruction::JpV0(nnn),
+            0xC000 => Instruction::RndVx(x, kk),
+            0xD000 => Instruction::DrwVxVy(x, y, n),
+            0xE000 => match kk {
+                0x9E => Instruction::SkpVx(x),
+                0xA1 => Instruction::SknpVx(x),
+                _ => return None,
+            },
+            0xF000 => match kk {
+                0x00 if x == 0 => Instruction::LdILong(next),
+                0x01 => Instruction::Plane(x),
+                0x02 if x == 0 => Instruction::LdAudioI,
+                0x07 => Instruction::LdVxDt(x),
+                0x0A => Instruction::LdVxK(x),
+                0x15 => Instruction::LdDtVx(x),
+                0x18 => Instruction::LdStVx(x),
+                0x1E => Instruction::AddIVx(x),
+                0x29 => Instruction::LdFVx(x),
+                0x30 => Instruction::LdHfVx(x),
+                0x33 => Instruction::LdBVx(x),
+                0x3A => Instruction::LdPitchVx(x),
+                0x55 => Instruction::LdIVx(x),
+                0x65 => Instruction::LdVxI(x),
+                0x75 => Instruction::LdRVx(x),
+                0x85 => Instruction::LdVxR(x),
+                _ => return None,
+            },
+            _ => return None,
+        };
+        (instruction.variant() <= variant).then_some(instruction)
+    }
+
+    // The first variant having this instruction
+    pub fn variant(&self) -> Variant {
+        match self {
+            Instruction::ScrollDown(_)
+            | Instruction::ScrollRight
+            | Instruction::ScrollLeft
+            | Instruction::Exit
+            | Instruction::Lores
+            | Instruction::Hires
+            | Instruction::LdHfVx(_)
+            | Instruction::LdRVx(_)
+            | Instruction::LdVxR(_) => Variant::Schip,
+            Instruction::ScrollUp(_)
+            | Instruction::LdIVxVy(..)
+            | Instruction::LdVxVyI(..)
+            | Instruction::LdILong(_)
+            | Instruction::Plane(_)
+            | Instruction::LdAudioI
+            | Instruction::LdPitchVx(_) => Variant::XoChip,
+            _ => Variant::Chip8,
+        }
+    }
+
+    // The bytes of the instruction, the inverse of decode. Fields are truncated to their size
+    pub fn encode(&self) -> Vec<u8> {
+        let xy = |base: u16, x: u8, y: u8| base | ((x as u16 & 0xF) << 8) | ((y as u16 & 0xF) << 4);
+        let xkk = |base: u16, x: u8, kk: u8| base | ((x as u16 & 0xF) << 8) | kk as u16;
+        let fx = |x: u8, kk: u16| 0xF000 | ((x as u16 & 0xF) << 8) | kk;
+        let opcode = match *self {
+            Instruction::Cls => 0x00E0,
+            Instruction::Ret => 0x00EE,
+            Instruction::Sys(nnn) => nnn & 0x0FFF,
+            Instruction::Jmp(nnn) => 0x1000 | (nnn & 0x0FFF),
+            Instruction::Call(nnn) => 0x2000 | (nnn & 0x0FFF),
+            Instruction::SeVx(x, kk) => xkk(0x3000, x, kk),
+            Instruction::SneVx(x, kk) => xkk(0x4000, x, kk),
+            Instruction::SeVxVy(x, y) => xy(0x5000, x, y),
+            Instruction::SneVxVy(x, y) => xy(0x9000, x, y),
+            Instruction::LdVx(x, kk) => xkk(0x6000, x, kk),
+            Instruction::AddVx(x, kk) => xkk(0x7000, x, kk),
+            Instruction::LdVxVy(x, y) => xy(0x8000, x, y),
+            Instruction::OrVxVy(x, y) => xy(0x8001, x, y),
+            Instruction::AndVxVy(x, y) => xy(0x8002, x, y),
+            Instruction::XorVxVy(x, y) => xy(0x8003, x, y),
+            Instruction::AddVxVy(x, y) => xy(0x8004, x, y),
+            Instruction::SubVxVy(x, y) => xy(0x8005, x, y),
+            Instruction::ShrVx(x, y) => xy(0x8006, x, y),
+            Instruction::SubnVxVy(x, y) => xy(0x8007, x, y),
+            Instruction::ShlVx(x, y) => xy(0x800E, x, y),
+            Instruction::LdI(nnn) => 0xA000 | (nnn & 0x0FFF),
+            Instruction::JpV0(nnn) => 0xB000 | (nnn & 0x0FFF),
+            Instruction::RndVx(x, kk) => xkk(0xC000, x, kk),
+            Instruction::DrwVxVy(x, y, n) => xy(0xD000, x, y) | (n as u16 & 0xF),
+            Instruction::SkpVx(x) => xkk(0xE000, x, 0x9E),
+            Instruction::SknpVx(x) => xkk(0xE000, x, 0xA1),
+            Instruction::LdVxDt(x) => fx(x, 0x07),
+            Instruction::LdVxK(x) => fx(x, 0x0A),
+            Instruction::LdDtVx(x) => fx(x, 0x15),
+            Instruction::LdStVx(x) => fx(x, 0x18),
+            Instruction::AddIVx(x) => fx(x, 0x1E),
+            Instruction::LdFVx(x) => fx(x, 0x29),
+            Instruction::LdBVx(x) => fx(x, 0x33),
+            Instruction::LdIVx(x) => fx(x, 0x55),
+            Instruction::LdVxI(x) => fx(x, 0x65),
+            Instruction::ScrollDown(n) => 0x00C0 | (n as u16 & 0xF),
+            Instruction::ScrollRight => 0x00FB,
+            Instruction::ScrollLeft => 0x00FC,
+            Instruction::Exit => 0x00FD,
+            Instruction::Lores => 0x00FE,
+            Instruction::Hires => 0x00FF,
+            Instruction::LdHfVx(x) => fx(x, 0x30),
+            Instruction::LdRVx(x) => fx(x, 0x75),
+            Instruction::LdVxR(x) => fx(x, 0x85),
+            Instruction::ScrollUp(n) => 0x00D0 | (n as u16 & 0xF),
+            Instruction::LdIVxVy(x, y) => xy(0x5002, x, y),
+            Instruction::LdVxVyI(x, y) => xy(0x5003, x, y),
+            Instruction::LdILong(nnnn) => {
+                return [0xF0, 0x00, (nnnn >> 8) as u8, nnnn as u8].to_vec()
+            }
+            Instruction::Plane(n) => fx(n, 0x01),
+            Instruction::LdAudioI => 0xF002,
+            Instruction::LdPitchVx(x) => fx(x, 0x3A),
+        };
+        opcode.to_be_bytes().to_vec()
+    }
+
+    // Length in bytes, F000 NNNN takes 4
+    pub fn size(&self) -> u16 {
+        match self {
+            Instruction::LdILong(_) => 4,
+            _ => 2,
+        }
+    }
+}
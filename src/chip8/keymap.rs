@@ -0,0 +1,143 @@
+// Importing useful modules
+use std::collections::HashMap;
+use std::fs;
+
+// Host keys of the 16 keypad keys in the order of the keypad layout, by physical position
+// on the left of the keyboard:
+// 1 2 3 4      1 2 3 C
+// Q W E R  ->  4 5 6 D
+// A S D F      7 8 9 E
+// Z X C V      A 0 B F
+const KEYPAD_LAYOUT: [u8; 16] = [
+    0x1, 0x2, 0x3, 0xC, 0x4, 0x5, 0x6, 0xD, 0x7, 0x8, 0x9, 0xE, 0xA, 0x0, 0xB, 0xF,
+];
+
+// Maps the keys of the host keyboard to the chip8 keypad (0x0-0xF).
+// Host keys are named by the lowercase character they type ("a", "1", ";"...),
+// or "space", "enter", "tab", "up", "down", "left" and "right"
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keymap {
+    keys: HashMap<String, u8>,
+}
+
+// Keymap methods
+impl Keymap {
+    // A keymap without any key
+    pub fn empty() -> Keymap {
+        Keymap {
+            keys: HashMap::new(),
+        }
+    }
+
+    // A keymap from the 16 host keys found at the positions of the keypad layout
+    fn from_layout(layout: [&str; 16]) -> Keymap {
+        let mut keymap = Keymap::empty();
+        for (host, key) in layout.iter().zip(KEYPAD_LAYOUT) {
+            keymap.bind(host, key);
+        }
+        keymap
+    }
+
+    // US and UK keyboards
+    pub fn qwerty() -> Keymap {
+        Keymap::from_layout([
+            "1", "2", "3", "4", "q", "w", "e", "r", "a", "s", "d", "f", "z", "x", "c", "v",
+        ])
+    }
+
+    // French keyboards, the top row also works without shift
+    pub fn azerty() -> Keymap {
+        let mut keymap = Keymap::from_layout([
+            "1", "2", "3", "4", "a", "z", "e", "r", "q", "s", "d", "f", "w", "x", "c", "v",
+        ]);
+        for (host, key) in ["&", "é", "\"", "'"].iter().zip(KEYPAD_LAYOUT) {
+            keymap.bind(host, key);
+        }
+        keymap
+    }
+
+    // Dvorak keyboards
+    pub fn dvorak() -> Keymap {
+        Keymap::from_layout([
+            "1", "2", "3", "4", "'", ",", ".", "p", "a", "o", "e", "u", ";", "q", "j", "k",
+        ])
+    }
+
+    // Get a preset by its name
+    pub fn from_name(name: &str) -> Option<Keymap> {
+        match name {
+            "qwerty" => Some(Keymap::qwerty()),
+            "azerty" => Some(Keymap::azerty()),
+            "dvorak" => Some(Keymap::dvorak()),
+            _ => None,
+        }
+    }
+
+    // Load a keymap from a config file, one keypad key per line followed by its host keys:
+    //   # Comments start with a sharp
+    //   preset = azerty   (optional, the keys to start from)
+    //   5 = z up
+    pub fn from_file(path: &str) -> Result<Keymap, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Can't open {}: {}", path, e))?;
+        Keymap::parse(&text).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    // Parse the text of a config file
+    pub fn parse(text: &str) -> Result<Keymap, String> {
+        let mut keymap = Keymap::empty();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let (name, hosts) = line
+                .split_once('=')
+                .ok_or(format!("line {}: expected <key> = <host keys>", number + 1))?;
+            let (name, hosts) = (name.trim(), hosts.trim());
+            if name == "preset" {
+                keymap = Keymap::from_name(hosts).ok_or(format!(
+                    "line {}: unknown preset {}",
+                    number + 1,
+                    hosts
+                ))?;
+                continue;
+            }
+
+            let key = u8::from_str_radix(name, 16)
+                .ok()
+                .filter(|key| *key < 16)
+                .ok_or(format!("line {}: invalid keypad key {}", number + 1, name))?;
+            // The host keys listed replace the ones of the preset
+            keymap.keys.retain(|_, k| *k != key);
+            for host in hosts.split_whitespace() {
+                keymap.bind(host, key);
+            }
+        }
+        Ok(keymap)
+    }
+
+    // Map a host key to a keypad key
+    pub fn bind(&mut self, host: &str, key: u8) {
+        self.keys.insert(host.to_lowercase(), key & 0xF);
+    }
+
+    // The keypad key of a host key, if mapped
+    pub fn key(&self, host: &str) -> Option<u8> {
+        self.keys.get(host).copied()
+    }
+
+    // The keypad key of a typed character, if mapped
+    pub fn key_for_char(&self, c: char) -> Option<u8> {
+        match c {
+            ' ' => self.key("space"),
+            _ => self.key(&c.to_lowercase().to_string()),
+        }
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Keymap::qwerty()
+    }
+}
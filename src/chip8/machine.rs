@@ -0,0 +1,193 @@
+// Importing useful modules
+use super::cpu::{Cpu, StepOutcome};
+use super::error::CpuError;
+use super::font::Font;
+use super::framebuffer::Framebuffer;
+use super::quirks::Quirks;
+use super::savestate::{CpuState, SaveStateError};
+use std::io;
+
+// Default number of instructions executed per 60 Hz frame
+const DEFAULT_INSTRUCTIONS_PER_FRAME: u32 = 10;
+
+// A whole chip8 machine, independent of any frontend
+pub struct Machine {
+    // The CPU running the program
+    cpu: Cpu,
+
+    // The program, kept to reset the machine
+    rom: Vec<u8>,
+
+    // The font loaded in memory
+    font: Font,
+
+    // Platform specific behaviours
+    quirks: Quirks,
+
+    // Number of instructions executed by run_frame
+    instructions_per_frame: u32,
+
+    // Seed of the random numbers generator, random on each reset if not set
+    seed: Option<u64>,
+}
+
+// Machine methods
+impl Machine {
+    // Create a machine with no program loaded, fails when the quirks give less memory than the
+    // interpreter area below 0x200
+    pub fn new(quirks: Quirks, font: Font) -> io::Result<Machine> {
+        let cpu = Cpu::new(&[], &font, quirks)?;
+        Ok(Machine {
+            cpu,
+            rom: Vec::new(),
+            font,
+            quirks,
+            instructions_per_frame: DEFAULT_INSTRUCTIONS_PER_FRAME,
+            seed: None,
+        })
+    }
+
+    // Load a program from its bytes and restart the machine on it
+    pub fn load(&mut self, rom: &[u8]) -> io::Result<()> {
+        self.cpu = Cpu::new(rom, &self.font, self.quirks)?;
+        self.rom = rom.to_vec();
+        if let Some(seed) = self.seed {
+            self.cpu.set_seed(seed);
+        }
+        Ok(())
+    }
+
+    // Restart the machine on the loaded program, fails when a loaded state switched to a memory
+    // too small for it
+    pub fn reset(&mut self) -> io::Result<()> {
+        self.cpu = Cpu::new(&self.rom, &self.font, self.quirks)?;
+        if let Some(seed) = self.seed {
+            self.cpu.set_seed(seed);
+        }
+        Ok(())
+    }
+
+    // Seed the random numbers generator, now and on each reset, so runs can be reproduced
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = Some(seed);
+        self.cpu.set_seed(seed);
+    }
+
+    // Snapshot of the whole machine
+    pub fn state(&self) -> CpuState {
+        self.cpu.state()
+    }
+
+    // Resume from a snapshot, the loaded rom is kept for resets
+    pub fn load_state(&mut self, state: CpuState) -> Result<(), SaveStateError> {
+        let quirks = state.quirks;
+        self.cpu = Cpu::from_state(state)?;
+        self.quirks = quirks;
+        Ok(())
+    }
+
+    // Execute one instruction
+    pub fn step(&mut self) -> Result<StepOutcome, CpuError> {
+        self.cpu.run()
+    }
+
+    // Execute one 60 Hz frame: up to instructions_per_frame instructions then a timers tick,
+    // the frame ends early when the CPU halts
+    pub fn run_frame(&mut self) -> Result<StepOutcome, CpuError> {
+        let mut outcome = StepOutcome::Executed;
+        for _ in 0..self.instructions_per_frame {
+            outcome = self.cpu.run()?;
+            if outcome != StepOutcome::Executed {
+                break;
+            }
+        }
+        self.cpu.tick_timers();
+        Ok(outcome)
+    }
+
+    // Decrement the delay and sound timers once, for callers stepping the machine themselves
+    pub fn tick_timers(&mut self) {
+        self.cpu.tick_timers();
+    }
+
+    // Update the state of a key of the keypad (0x0-0xF)
+    pub fn set_key(&mut self, key: u8, pressed: bool) {
+        self.cpu.set_key(key, pressed);
+    }
+
+    // If the buzzer sounds
+    pub fn sound_active(&self) -> bool {
+        self.cpu.sound_active()
+    }
+
+    // Getting the XO-CHIP audio pattern buffer, if the program loaded one
+    pub fn audio_pattern(&self) -> Option<&[u8; 16]> {
+        self.cpu.audio_pattern()
+    }
+
+    // Getting the XO-CHIP audio pattern pitch
+    pub fn pitch(&self) -> u8 {
+        self.cpu.pitch()
+    }
+
+    // Getting the current screen
+    pub fn framebuffer(&self) -> &Framebuffer {
+        self.cpu.framebuffer()
+    }
+
+    // Number of instructions executed per frame
+    pub fn instructions_per_frame(&self) -> u32 {
+        self.instructions_per_frame
+    }
+
+    // Change the number of instructions executed per frame
+    pub fn set_instructions_per_frame(&mut self, instructions: u32) {
+        self.instructions_per_frame = instructions;
+    }
+
+    // Getting the CPU
+    pub fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    // Getting the CPU to change its registers or memory, e.g. from a debugger
+    pub fn cpu_mut(&mut self) -> &mut Cpu {
+        &mut self.cpu
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn new_needs_the_interpreter_area() {
+        let mut quirks = Quirks::vip();
+        quirks.memory_size = 0x100;
+        assert!(Machine::new(quirks, Font::Chip8).is_err());
+    }
+
+    #[test]
+    fn reset_restarts_the_program() {
+        // 6007: v0 := 7, then 1202: loop
+        let mut machine = Machine::new(Quirks::vip(), Font::Chip8).unwrap();
+        machine.load(&[0x60, 0x07, 0x12, 0x02]).unwrap();
+        machine.run_frame().unwrap();
+        assert_eq!(machine.cpu().registers()[0], 7);
+        machine.reset().unwrap();
+        assert_eq!(machine.cpu().registers()[0], 0);
+        assert_eq!(machine.cpu().pc(), 0x200);
+    }
+
+    #[test]
+    fn reset_fails_when_the_rom_no_longer_fits() {
+        // A state with less memory than the loaded rom needs
+        let mut quirks = Quirks::xochip();
+        quirks.memory_size = 0x300;
+        let small = Machine::new(quirks, Font::Chip8).unwrap();
+        let mut machine = Machine::new(Quirks::xochip(), Font::Chip8).unwrap();
+        machine.load(&[0x12, 0x00].repeat(0x100)).unwrap();
+        machine.load_state(small.state()).unwrap();
+        assert!(machine.reset().is_err());
+    }
+}
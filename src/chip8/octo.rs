@@ -0,0 +1,1137 @@
+// Importing useful modules
+use super::asm::{AsmError, ORIGIN};
+use super::instruction::Instruction;
+use std::cell::Cell;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs;
+
+// Most macro expansions in a program, which also stops a macro expanding itself forever
+const MAX_MACRO_EXPANSIONS: usize = 65536;
+
+// Words with a meaning of their own, they can't name labels, constants, aliases or macros
+const KEYWORDS: [&str; 45] = [
+    ":", ":=", "+=", "-=", "=-", "|=", "&=", "^=", ">>=", "<<=", "==", "!=", "<", ">", "<=", ">=",
+    "-", ";", "clear", "return", "bcd", "save", "load", "sprite", "jump", "jump0", "native",
+    "delay", "buzzer", "pitch", "random", "key", "-key", "hex", "bighex", "long", "if", "then",
+    "begin", "else", "end", "loop", "again", "while", "i",
+];
+
+// A compiled program: the bytes loaded at 0x200, and the adresses of its labels
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Program {
+    pub rom: Vec<u8>,
+    pub labels: BTreeMap<String, u16>,
+}
+
+// A word of the source, Octo only splits on whitespace
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Token {
+    text: String,
+
+    // Counted from 1
+    line: usize,
+    column: usize,
+}
+
+// A macro: its parameters and the words replacing it
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Token>,
+}
+
+// A reference to a label not defined yet, filled in once the whole program is compiled
+struct Patch {
+    // Adress of the instruction (12 bits adress) or of the 16 bits adress (long)
+    adress: usize,
+    long: bool,
+    label: Token,
+}
+
+// A block opened by if ... begin or loop
+enum Block {
+    // Adress of the jump skipping the block (to else or end)
+    If {
+        jump: usize,
+        token: Token,
+    },
+
+    // Adress of the start of the loop, and of the jumps leaving it (while)
+    Loop {
+        start: u16,
+        exits: Vec<usize>,
+        token: Token,
+    },
+}
+
+// A register or a value on the right of an operator
+enum Operand {
+    Register(u8),
+    Value(Token),
+}
+
+// Compile an Octo source file into a program
+pub fn compile_file(path: &str) -> Result<Program, AsmError> {
+    let text = fs::read_to_string(path).map_err(|e| AsmError {
+        file: path.to_string(),
+        line: 0,
+        column: 0,
+        message: format!("Can't open: {}", e),
+        source: String::new(),
+    })?;
+    compile(&text, path)
+}
+
+// Compile Octo source text into a program, the path names the source in the errors.
+// Execution starts at the main label: a jump to it is placed at 0x200, unless main comes first
+pub fn compile(text: &str, path: &str) -> Result<Program, AsmError> {
+    let mut compiler = Compiler {
+        file: path,
+        lines: text.lines().collect(),
+        tokens: tokenize(text),
+        last: Token {
+            text: String::new(),
+            line: 1,
+            column: 1,
+        },
+        rom: Vec::new(),
+        here: ORIGIN as usize,
+        labels: BTreeMap::new(),
+        constants: HashMap::new(),
+        aliases: HashMap::new(),
+        macros: HashMap::new(),
+        patches: Vec::new(),
+        blocks: Vec::new(),
+        expansions: 0,
+        main: None,
+        here_used: Cell::new(false),
+    };
+    compiler.compile()
+}
+
+// Split the source into words, a sharp starts a comment
+fn tokenize(text: &str) -> VecDeque<Token> {
+    let mut tokens = VecDeque::new();
+    for (number, line) in text.lines().enumerate() {
+        let mut chars = line.char_indices().peekable();
+        while let Some((start, c)) = chars.next() {
+            if c.is_whitespace() {
+                continue;
+            }
+            if c == '#' {
+                break;
+            }
+            let mut end = start + c.len_utf8();
+            while let Some((i, c)) = chars.peek().copied() {
+                if c.is_whitespace() {
+                    break;
+                }
+                end = i + c.len_utf8();
+                chars.next();
+            }
+            tokens.push_back(Token {
+                text: line[start..end].to_string(),
+                line: number + 1,
+                column: line[..start].chars().count() + 1,
+            });
+        }
+    }
+    tokens
+}
+
+// Numbers are decimal, hexadecimal (0xFF) or binary (0b1010), optionally negative
+fn parse_number(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()?
+    } else {
+        digits.parse().ok()?
+    };
+    Some(if negative { -value } else { value })
+}
+
+// Index of a register name, v0 to vF
+fn register_index(name: &str) -> Option<u8> {
+    let mut chars = name.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some('v' | 'V'), Some(digit), None) => digit.to_digit(16).map(|index| index as u8),
+        _ => None,
+    }
+}
+
+// The compiler state, the program is compiled in a single pass, labels used before being
+// defined are patched at the end
+struct Compiler<'a> {
+    // Name of the source in the errors, and its lines
+    file: &'a str,
+    lines: Vec<&'a str>,
+
+    // Words left to compile, macros expand at the front
+    tokens: VecDeque<Token>,
+
+    // The last word read, where errors at the end of the source point
+    last: Token,
+
+    // The bytes from 0x200, and the adress of the next one
+    rom: Vec<u8>,
+    here: usize,
+
+    // Labels, constants (:const and :calc), register aliases and macros
+    labels: BTreeMap<String, u16>,
+    constants: HashMap<String, i64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+
+    // References to labels not defined yet
+    patches: Vec<Patch>,
+
+    // Blocks not closed yet, the innermost last
+    blocks: Vec<Block>,
+
+    // Number of macro expansions so far
+    expansions: usize,
+
+    // Where main is defined, the jump to it may not reach it
+    main: Option<Token>,
+
+    // Set once the current adress is used (by a label or HERE), the jump to main can't be
+    // dropped anymore without moving them
+    here_used: Cell<bool>,
+}
+
+// Compiler methods
+impl Compiler<'_> {
+    // Compile the whole source
+    fn compile(&mut self) -> Result<Program, AsmError> {
+        // Room for the jump to main
+        self.write(&[0, 0], &self.last.clone())?;
+        let mut main_jump = true;
+
+        while let Some(token) = self.tokens.pop_front() {
+            self.last = token.clone();
+
+            // main coming first needs no jump
+            if token.text == ":"
+                && self.here == ORIGIN as usize + 2
+                && main_jump
+                && !self.here_used.get()
+                && self.tokens.front().is_some_and(|t| t.text == "main")
+            {
+                self.rom.clear();
+                self.here = ORIGIN as usize;
+                main_jump = false;
+            }
+            self.statement(token)?;
+        }
+
+        if let Some(block) = self.blocks.pop() {
+            let (token, message) = match block {
+                Block::If { token, .. } => (token, "This if has no end"),
+                Block::Loop { token, .. } => (token, "This loop has no again"),
+            };
+            return Err(self.error(&token, message.to_string()));
+        }
+
+        // The jump to main, then the labels used before being defined
+        if main_jump {
+            let main = *self.labels.get("main").ok_or_else(|| AsmError {
+                file: self.file.to_string(),
+                line: 0,
+                column: 0,
+                message: "The program has no main label".into(),
+                source: String::new(),
+            })?;
+            let token = self.main.clone().unwrap_or_else(|| self.last.clone());
+            let main = self.jump_target(main as usize, &token)?;
+            self.rom[..2].copy_from_slice(&Instruction::Jmp(main).encode());
+        }
+        for patch in std::mem::take(&mut self.patches) {
+            let target = *self.labels.get(&patch.label.text).ok_or_else(|| {
+                self.error(&patch.label, format!("Unknown label {}", patch.label.text))
+            })?;
+            let offset = patch.adress - ORIGIN as usize;
+            if patch.long {
+                self.rom[offset..offset + 2].copy_from_slice(&target.to_be_bytes());
+            } else {
+                if target > 0x0FFF {
+                    return Err(self.error(
+                        &patch.label,
+                        format!("{} is past 0xFFF, use i := long", patch.label.text),
+                    ));
+                }
+                self.rom[offset] |= (target >> 8) as u8;
+                self.rom[offset + 1] = target as u8;
+            }
+        }
+
+        Ok(Program {
+            rom: std::mem::take(&mut self.rom),
+            labels: std::mem::take(&mut self.labels),
+        })
+    }
+
+    // An error at a word, quoting its line
+    fn error(&self, token: &Token, message: String) -> AsmError {
+        AsmError {
+            file: self.file.to_string(),
+            line: token.line,
+            column: token.column,
+            message,
+            source: self
+                .lines
+                .get(token.line - 1)
+                .map_or(String::new(), |line| line.to_string()),
+        }
+    }
+
+    // The next word
+    fn next(&mut self) -> Result<Token, AsmError> {
+        let token = self
+            .tokens
+            .pop_front()
+            .ok_or_else(|| self.error(&self.last, "Unexpected end of the source".into()))?;
+        self.last = token.clone();
+        Ok(token)
+    }
+
+    // The next word, which must be the given one
+    fn expect(&mut self, text: &str) -> Result<Token, AsmError> {
+        let token = self.next()?;
+        if token.text != text {
+            return Err(self.error(&token, format!("Expected {}, not {}", text, token.text)));
+        }
+        Ok(token)
+    }
+
+    // The words up to the closing brace, the opening one being the next word
+    fn braces(&mut self) -> Result<Vec<Token>, AsmError> {
+        self.expect("{")?;
+        let mut depth = 1;
+        let mut body = Vec::new();
+        loop {
+            let token = self.next()?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(body);
+                    }
+                }
+                _ => {}
+            }
+            body.push(token);
+        }
+    }
+
+    // Write bytes at the current adress
+    fn write(&mut self, bytes: &[u8], token: &Token) -> Result<(), AsmError> {
+        if self.here + bytes.len() > 0x10000 {
+            return Err(self.error(token, "The program doesn't fit in memory".into()));
+        }
+        let offset = self.here - ORIGIN as usize;
+        if self.rom.len() < offset + bytes.len() {
+            self.rom.resize(offset + bytes.len(), 0);
+        }
+        self.rom[offset..offset + bytes.len()].copy_from_slice(bytes);
+        self.here += bytes.len();
+        Ok(())
+    }
+
+    // Write an instruction at the current adress
+    fn emit(&mut self, instruction: Instruction, token: &Token) -> Result<(), AsmError> {
+        self.write(&instruction.encode(), token)
+    }
+
+    // An adress jumps can reach, the token is where the error points
+    fn jump_target(&self, target: usize, token: &Token) -> Result<u16, AsmError> {
+        if target > 0x0FFF {
+            return Err(self.error(
+                token,
+                format!("0x{:X} is past 0xFFF, jumps can't reach it", target),
+            ));
+        }
+        Ok(target as u16)
+    }
+
+    // Point the jump at an adress to the current adress
+    fn patch_jump(&mut self, adress: usize, token: &Token) -> Result<(), AsmError> {
+        let target = self.jump_target(self.here, token)?;
+        let offset = adress - ORIGIN as usize;
+        self.rom[offset] = 0x10 | (target >> 8) as u8;
+        self.rom[offset + 1] = target as u8;
+        Ok(())
+    }
+
+    // A name for a label, constant, alias or macro
+    fn name(&mut self) -> Result<Token, AsmError> {
+        let token = self.next()?;
+        let taken = KEYWORDS.contains(&token.text.as_str())
+            || register_index(&token.text).is_some()
+            || parse_number(&token.text).is_some()
+            || token.text.starts_with(':')
+            || token.text == "{"
+            || token.text == "}";
+        if taken {
+            return Err(self.error(&token, format!("{} can't be used as a name", token.text)));
+        }
+        Ok(token)
+    }
+
+    // The register a word names, v0-vF or an alias
+    fn register_of(&self, token: &Token) -> Option<u8> {
+        register_index(&token.text).or_else(|| self.aliases.get(&token.text).copied())
+    }
+
+    // The next word, which must be a register
+    fn register(&mut self) -> Result<u8, AsmError> {
+        let token = self.next()?;
+        self.register_of(&token)
+            .ok_or_else(|| self.error(&token, format!("Expected a register, not {}", token.text)))
+    }
+
+    // A register, or any other word as a value
+    fn operand(&mut self) -> Result<Operand, AsmError> {
+        let token = self.next()?;
+        Ok(match self.register_of(&token) {
+            Some(index) => Operand::Register(index),
+            None => Operand::Value(token),
+        })
+    }
+
+    // The value of a word: a number, a constant or a label already defined
+    fn number(&self, token: &Token) -> Result<i64, AsmError> {
+        if let Some(value) = parse_number(&token.text) {
+            return Ok(value);
+        }
+        if let Some(value) = self.constants.get(&token.text) {
+            return Ok(*value);
+        }
+        if let Some(adress) = self.labels.get(&token.text) {
+            return Ok(*adress as i64);
+        }
+        Err(self.error(token, format!("Unknown value {}", token.text)))
+    }
+
+    // The value of a word checked against the size of its field, bytes may be negative
+    fn value(&self, token: &Token, bits: u32) -> Result<u16, AsmError> {
+        let value = self.number(token)?;
+        let max = (1 << bits) - 1;
+        let min = if bits == 8 { -128 } else { 0 };
+        if value < min || value > max {
+            return Err(self.error(token, format!("{} doesn't fit in {} bits", value, bits)));
+        }
+        Ok((value & max) as u16)
+    }
+
+    // The next word as an adress, a label not defined yet is patched at the end.
+    // The instruction using it must be the next one written
+    fn adress(&mut self, long: bool) -> Result<u16, AsmError> {
+        let token = self.next()?;
+        let bits = if long { 16 } else { 12 };
+        if parse_number(&token.text).is_some()
+            || self.constants.contains_key(&token.text)
+            || self.labels.contains_key(&token.text)
+        {
+            return self.value(&token, bits);
+        }
+        self.patches.push(Patch {
+            adress: if long { self.here + 2 } else { self.here },
+            long,
+            label: token,
+        });
+        Ok(0)
+    }
+
+    // Compile a statement starting with a word
+    fn statement(&mut self, token: Token) -> Result<(), AsmError> {
+        match token.text.as_str() {
+            // Directives
+            ":" => {
+                let name = self.name()?;
+                if self.labels.contains_key(&name.text) {
+                    return Err(self.error(&name, format!("{} is already defined", name.text)));
+                }
+                if name.text == "main" {
+                    self.main = Some(name.clone());
+                }
+                self.here_used.set(true);
+                self.labels.insert(name.text, self.here as u16);
+            }
+            ":alias" => {
+                let name = self.name()?;
+                let register = self.register()?;
+                self.aliases.insert(name.text, register);
+            }
+            ":const" => {
+                let name = self.name()?;
+                let value = self.next()?;
+                let value = self.number(&value)?;
+                self.constants.insert(name.text, value);
+            }
+            ":calc" => {
+                let name = self.name()?;
+                let expression = self.braces()?;
+                let value = self.calc(&expression, &name)?;
+                self.constants.insert(name.text, value);
+            }
+            ":macro" => {
+                let name = self.name()?;
+                let mut params = Vec::new();
+                while self.tokens.front().is_some_and(|t| t.text != "{") {
+                    params.push(self.name()?.text);
+                }
+                let body = self.braces()?;
+                self.macros.insert(name.text, Macro { params, body });
+            }
+            ":call" => {
+                let adress = self.adress(false)?;
+                self.emit(Instruction::Call(adress), &token)?;
+            }
+            ":byte" => {
+                let value = if self.tokens.front().is_some_and(|t| t.text == "{") {
+                    let expression = self.braces()?;
+                    self.calc(&expression, &token)?
+                } else {
+                    let value = self.next()?;
+                    self.value(&value, 8)? as i64
+                };
+                self.write(&[value as u8], &token)?;
+            }
+            ":org" => {
+                let adress = self.next()?;
+                let adress = self.value(&adress, 16)? as usize;
+                if adress < ORIGIN as usize {
+                    return Err(self.error(&token, "Programs start at 0x200".into()));
+                }
+                self.here = adress;
+            }
+
+            // Instructions without operands
+            "clear" => self.emit(Instruction::Cls, &token)?,
+            "return" | ";" => self.emit(Instruction::Ret, &token)?,
+            "exit" => self.emit(Instruction::Exit, &token)?,
+            "lores" => self.emit(Instruction::Lores, &token)?,
+            "hires" => self.emit(Instruction::Hires, &token)?,
+            "scroll-right" => self.emit(Instruction::ScrollRight, &token)?,
+            "scroll-left" => self.emit(Instruction::ScrollLeft, &token)?,
+            "audio" => self.emit(Instruction::LdAudioI, &token)?,
+
+            // Instructions with operands
+            "scroll-down" | "scroll-up" | "plane" => {
+                let n = self.next()?;
+                let n = self.value(&n, 4)? as u8;
+                let instruction = match token.text.as_str() {
+                    "scroll-down" => Instruction::ScrollDown(n),
+                    "scroll-up" => Instruction::ScrollUp(n),
+                    _ => Instruction::Plane(n),
+                };
+                self.emit(instruction, &token)?;
+            }
+            "bcd" => {
+                let x = self.register()?;
+                self.emit(Instruction::LdBVx(x), &token)?;
+            }
+            "saveflags" => {
+                let x = self.register()?;
+                self.emit(Instruction::LdRVx(x), &token)?;
+            }
+            "loadflags" => {
+                let x = self.register()?;
+                self.emit(Instruction::LdVxR(x), &token)?;
+            }
+            "save" | "load" => {
+                let x = self.register()?;
+                let save = token.text == "save";
+                let instruction = if self.tokens.front().is_some_and(|t| t.text == "-") {
+                    self.next()?;
+                    let y = self.register()?;
+                    if save {
+                        Instruction::LdIVxVy(x, y)
+                    } else {
+                        Instruction::LdVxVyI(x, y)
+                    }
+                } else if save {
+                    Instruction::LdIVx(x)
+                } else {
+                    Instruction::LdVxI(x)
+                };
+                self.emit(instruction, &token)?;
+            }
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.next()?;
+                let n = self.value(&n, 4)? as u8;
+                self.emit(Instruction::DrwVxVy(x, y, n), &token)?;
+            }
+            "jump" => {
+                let adress = self.adress(false)?;
+                self.emit(Instruction::Jmp(adress), &token)?;
+            }
+            "jump0" => {
+                let adress = self.adress(false)?;
+                self.emit(Instruction::JpV0(adress), &token)?;
+            }
+            "native" => {
+                let adress = self.adress(false)?;
+                self.emit(Instruction::Sys(adress), &token)?;
+            }
+            "i" => self.i_assignment(&token)?,
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.register()?;
+                let instruction = match token.text.as_str() {
+                    "delay" => Instruction::LdDtVx(x),
+                    "buzzer" => Instruction::LdStVx(x),
+                    _ => Instruction::LdPitchVx(x),
+                };
+                self.emit(instruction, &token)?;
+            }
+
+            // Control flow
+            "if" => {
+                let condition = self.condition_tokens()?;
+                let word = self.next()?;
+                match word.text.as_str() {
+                    "then" => self.condition(&condition, false)?,
+                    "begin" => {
+                        self.condition(&condition, true)?;
+                        let jump = self.here;
+                        self.emit(Instruction::Jmp(0), &word)?;
+                        self.blocks.push(Block::If { jump, token });
+                    }
+                    _ => {
+                        return Err(
+                            self.error(&word, format!("Expected then or begin, not {}", word.text))
+                        )
+                    }
+                }
+            }
+            "else" => match self.blocks.pop() {
+                Some(Block::If { jump, token: start }) => {
+                    let end = self.here;
+                    self.emit(Instruction::Jmp(0), &token)?;
+                    self.patch_jump(jump, &token)?;
+                    self.blocks.push(Block::If {
+                        jump: end,
+                        token: start,
+                    });
+                }
+                _ => return Err(self.error(&token, "else without if ... begin".into())),
+            },
+            "end" => match self.blocks.pop() {
+                Some(Block::If { jump, .. }) => self.patch_jump(jump, &token)?,
+                _ => return Err(self.error(&token, "end without if ... begin".into())),
+            },
+            "loop" => self.blocks.push(Block::Loop {
+                start: self.here as u16,
+                exits: Vec::new(),
+                token,
+            }),
+            "while" => {
+                if !self.blocks.iter().any(|b| matches!(b, Block::Loop { .. })) {
+                    return Err(self.error(&token, "while outside of a loop".into()));
+                }
+                let condition = self.condition_tokens()?;
+                self.condition(&condition, true)?;
+                let exit = self.here;
+                self.emit(Instruction::Jmp(0), &token)?;
+                for block in self.blocks.iter_mut().rev() {
+                    if let Block::Loop { exits, .. } = block {
+                        exits.push(exit);
+                        break;
+                    }
+                }
+            }
+            "again" => match self.blocks.pop() {
+                Some(Block::Loop { start, exits, .. }) => {
+                    let start = self.jump_target(start as usize, &token)?;
+                    self.emit(Instruction::Jmp(start), &token)?;
+                    for exit in exits {
+                        self.patch_jump(exit, &token)?;
+                    }
+                }
+                _ => return Err(self.error(&token, "again without loop".into())),
+            },
+
+            // Registers assignments, macros, data bytes and subroutine calls
+            _ if self.register_of(&token).is_some() => self.register_assignment(&token)?,
+            _ if self.macros.contains_key(&token.text) => self.expand(&token)?,
+            _ if parse_number(&token.text).is_some()
+                || self.constants.contains_key(&token.text) =>
+            {
+                let value = self.value(&token, 8)?;
+                self.write(&[value as u8], &token)?;
+            }
+            text if text.starts_with(':') || KEYWORDS.contains(&text) => {
+                return Err(self.error(&token, format!("Unsupported {}", text)))
+            }
+            _ => {
+                self.tokens.push_front(token.clone());
+                let adress = self.adress(false)?;
+                self.emit(Instruction::Call(adress), &token)?;
+            }
+        }
+        Ok(())
+    }
+
+    // i := adress, i := long adress, i := hex vX, i := bighex vX or i += vX
+    fn i_assignment(&mut self, token: &Token) -> Result<(), AsmError> {
+        let operator = self.next()?;
+        let instruction = match operator.text.as_str() {
+            "+=" => Instruction::AddIVx(self.register()?),
+            ":=" => match self.tokens.front().map(|t| t.text.as_str()) {
+                Some("hex") => {
+                    self.next()?;
+                    Instruction::LdFVx(self.register()?)
+                }
+                Some("bighex") => {
+                    self.next()?;
+                    Instruction::LdHfVx(self.register()?)
+                }
+                Some("long") => {
+                    self.next()?;
+                    Instruction::LdILong(self.adress(true)?)
+                }
+                _ => Instruction::LdI(self.adress(false)?),
+            },
+            _ => {
+                return Err(self.error(
+                    &operator,
+                    format!("Expected := or +=, not {}", operator.text),
+                ))
+            }
+        };
+        self.emit(instruction, token)
+    }
+
+    // vX followed by an operator and its operand
+    fn register_assignment(&mut self, token: &Token) -> Result<(), AsmError> {
+        let x = self.register_of(token).unwrap_or(0);
+        let operator = self.next()?;
+        let instruction = match (operator.text.as_str(), self.operand()?) {
+            (":=", Operand::Register(y)) => Instruction::LdVxVy(x, y),
+            (":=", Operand::Value(word)) if word.text == "random" => {
+                let mask = self.next()?;
+                Instruction::RndVx(x, self.value(&mask, 8)? as u8)
+            }
+            (":=", Operand::Value(word)) if word.text == "delay" => Instruction::LdVxDt(x),
+            (":=", Operand::Value(word)) if word.text == "key" => Instruction::LdVxK(x),
+            (":=", Operand::Value(value)) => Instruction::LdVx(x, self.value(&value, 8)? as u8),
+            ("+=", Operand::Register(y)) => Instruction::AddVxVy(x, y),
+            ("+=", Operand::Value(value)) => Instruction::AddVx(x, self.value(&value, 8)? as u8),
+            ("-=", Operand::Register(y)) => Instruction::SubVxVy(x, y),
+            // Subtracting a value adds its opposite
+            ("-=", Operand::Value(value)) => {
+                Instruction::AddVx(x, (self.value(&value, 8)? as u8).wrapping_neg())
+            }
+            ("=-", Operand::Register(y)) => Instruction::SubnVxVy(x, y),
+            ("|=", Operand::Register(y)) => Instruction::OrVxVy(x, y),
+            ("&=", Operand::Register(y)) => Instruction::AndVxVy(x, y),
+            ("^=", Operand::Register(y)) => Instruction::XorVxVy(x, y),
+            (">>=", Operand::Register(y)) => Instruction::ShrVx(x, y),
+            ("<<=", Operand::Register(y)) => Instruction::ShlVx(x, y),
+            (_, Operand::Value(value)) if operator.text.ends_with('=') => {
+                return Err(self.error(
+                    &value,
+                    format!("{} needs a register, not {}", operator.text, value.text),
+                ))
+            }
+            _ => return Err(self.error(&operator, format!("Unknown operator {}", operator.text))),
+        };
+        self.emit(instruction, token)
+    }
+
+    // The words of a condition: vX key, vX -key, or vX <comparison> <register or value>
+    fn condition_tokens(&mut self) -> Result<Vec<Token>, AsmError> {
+        let mut condition = vec![self.next()?, self.next()?];
+        if condition[1].text != "key" && condition[1].text != "-key" {
+            condition.push(self.next()?);
+        }
+        Ok(condition)
+    }
+
+    // Emit the skip making the next instruction run only when the condition holds (or only when
+    // it doesn't, if negated). Comparisons other than == and != compute their result in vF
+    fn condition(&mut self, condition: &[Token], negated: bool) -> Result<(), AsmError> {
+        let x = self.register_of(&condition[0]).ok_or_else(|| {
+            self.error(
+                &condition[0],
+                format!("Expected a register, not {}", condition[0].text),
+            )
+        })?;
+        let operator = &condition[1];
+        let operand = condition.get(2).map(|token| match self.register_of(token) {
+            Some(index) => Operand::Register(index),
+            None => Operand::Value(token.clone()),
+        });
+        let instructions = match (operator.text.as_str(), operand) {
+            ("key", _) if negated => vec![Instruction::SkpVx(x)],
+            ("key", _) => vec![Instruction::SknpVx(x)],
+            ("-key", _) if negated => vec![Instruction::SknpVx(x)],
+            ("-key", _) => vec![Instruction::SkpVx(x)],
+            (op @ ("==" | "!="), Some(operand)) => {
+                // The next instruction runs when equal: skip when not equal
+                let equal = (op == "==") != negated;
+                match operand {
+                    Operand::Register(y) if equal => vec![Instruction::SneVxVy(x, y)],
+                    Operand::Register(y) => vec![Instruction::SeVxVy(x, y)],
+                    Operand::Value(value) => {
+                        let value = self.value(&value, 8)? as u8;
+                        if equal {
+                            vec![Instruction::SneVx(x, value)]
+                        } else {
+                            vec![Instruction::SeVx(x, value)]
+                        }
+                    }
+                }
+            }
+            (op @ ("<" | ">" | "<=" | ">="), Some(operand)) => {
+                // vF is 1 when vX >= y (<, >=), or when vX <= y (>, <=), from the borrow of a subtraction
+                let less_or_equal = op == ">" || op == "<=";
+                let mut instructions = match (operand, less_or_equal) {
+                    (Operand::Register(y), false) => {
+                        vec![Instruction::LdVxVy(0xF, x), Instruction::SubVxVy(0xF, y)]
+                    }
+                    (Operand::Register(y), true) => {
+                        vec![Instruction::LdVxVy(0xF, y), Instruction::SubVxVy(0xF, x)]
+                    }
+                    (Operand::Value(value), false) => {
+                        let value = self.value(&value, 8)? as u8;
+                        vec![Instruction::LdVx(0xF, value), Instruction::SubnVxVy(0xF, x)]
+                    }
+                    (Operand::Value(value), true) => {
+                        let value = self.value(&value, 8)? as u8;
+                        vec![Instruction::LdVx(0xF, value), Instruction::SubVxVy(0xF, x)]
+                    }
+                };
+                let wanted = (op == ">=" || op == "<=") != negated;
+                instructions.push(Instruction::SneVx(0xF, wanted as u8));
+                instructions
+            }
+            _ => return Err(self.error(operator, format!("Unknown comparison {}", operator.text))),
+        };
+        for instruction in instructions {
+            self.emit(instruction, operator)?;
+        }
+        Ok(())
+    }
+
+    // Replace a macro by its body, its parameters replaced by the words following it
+    fn expand(&mut self, token: &Token) -> Result<(), AsmError> {
+        self.expansions += 1;
+        if self.expansions > MAX_MACRO_EXPANSIONS {
+            return Err(self.error(token, "Too many macro expansions".into()));
+        }
+        let (params, body) = match self.macros.get(&token.text) {
+            Some(definition) => (definition.params.clone(), definition.body.clone()),
+            None => return Ok(()),
+        };
+        let mut arguments = HashMap::new();
+        for param in params {
+            let argument = self.next()?;
+            arguments.insert(param, argument.text);
+        }
+        for mut word in body.into_iter().rev() {
+            if let Some(argument) = arguments.get(&word.text) {
+                word.text = argument.clone();
+            }
+            self.tokens.push_front(word);
+        }
+        Ok(())
+    }
+
+    // Evaluate a :calc expression. Like Octo, operators have no precedence and are evaluated
+    // from right to left, parentheses group
+    fn calc(&self, tokens: &[Token], at: &Token) -> Result<i64, AsmError> {
+        let mut position = 0;
+        let value = self.calc_expression(tokens, &mut position, at)?;
+        match tokens.get(position) {
+            Some(token) => Err(self.error(token, format!("Unexpected {}", token.text))),
+            None => Ok(value),
+        }
+    }
+
+    fn calc_expression(
+        &self,
+        tokens: &[Token],
+        position: &mut usize,
+        at: &Token,
+    ) -> Result<i64, AsmError> {
+        let left = self.calc_term(tokens, position, at)?;
+        let operator = match tokens.get(*position) {
+            Some(token) if token.text != ")" => token,
+            _ => return Ok(left),
+        };
+        *position += 1;
+        let right = self.calc_expression(tokens, position, at)?;
+        let zero = || self.error(operator, "Division by zero".into());
+        Ok(match operator.text.as_str() {
+            "+" => left.wrapping_add(right),
+            "-" => left.wrapping_sub(right),
+            "*" => left.wrapping_mul(right),
+            "/" => left.checked_div(right).ok_or_else(zero)?,
+            "%" => left.checked_rem(right).ok_or_else(zero)?,
+            "&" => left & right,
+            "|" => left | right,
+            "^" => left ^ right,
+            "<<" => left.wrapping_shl(right as u32),
+            ">>" => left.wrapping_shr(right as u32),
+            _ => return Err(self.error(operator, format!("Unknown operator {}", operator.text))),
+        })
+    }
+
+    fn calc_term(
+        &self,
+        tokens: &[Token],
+        position: &mut usize,
+        at: &Token,
+    ) -> Result<i64, AsmError> {
+        let token = tokens
+            .get(*position)
+            .ok_or_else(|| self.error(at, "Missing value in the expression".into()))?;
+        *position += 1;
+        match token.text.as_str() {
+            "(" => {
+                let value = self.calc_expression(tokens, position, at)?;
+                match tokens.get(*position) {
+                    Some(close) if close.text == ")" => {
+                        *position += 1;
+                        Ok(value)
+                    }
+                    _ => Err(self.error(token, "Missing )".into())),
+                }
+            }
+            "-" => Ok(-self.calc_term(tokens, position, at)?),
+            "~" => Ok(!self.calc_term(tokens, position, at)?),
+            "!" => Ok((self.calc_term(tokens, position, at)? == 0) as i64),
+            "HERE" => {
+                self.here_used.set(true);
+                Ok(self.here as i64)
+            }
+            _ => self.number(token),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::cpu::StepOutcome;
+    use crate::chip8::font::Font;
+    use crate::chip8::machine::Machine;
+    use crate::chip8::quirks::Quirks;
+
+    fn rom(source: &str) -> Vec<u8> {
+        compile(source, "test.8o").unwrap().rom
+    }
+
+    fn compile_error(source: &str) -> AsmError {
+        compile(source, "test.8o").unwrap_err()
+    }
+
+    // Run a program until it exits, returns its registers
+    fn registers(source: &str) -> Vec<u8> {
+        let mut machine = Machine::new(Quirks::vip(), Font::Chip8).unwrap();
+        machine.load(&rom(source)).unwrap();
+        for _ in 0..1000 {
+            if machine.step().unwrap() == StepOutcome::Exit {
+                return machine.cpu().registers().to_vec();
+            }
+        }
+        panic!("The program doesn't exit: {}", source);
+    }
+
+    #[test]
+    fn labels_and_forward_references() {
+        let program = compile(
+            ": main\n  i := data\n  sub\n  jump done\n: sub\n  :call sub\n  ;\n: done\n  i := long data\n: data\n  1 2 0xFF\n",
+            "test.8o",
+        )
+        .unwrap();
+        assert_eq!(
+            program.rom,
+            [
+                0xA2, 0x0E, 0x22, 0x06, 0x12, 0x0A, 0x22, 0x06, 0x00, 0xEE, 0xF0, 0x00, 0x02, 0x0E,
+                0x01, 0x02, 0xFF
+            ]
+        );
+        let labels: Vec<(&str, u16)> = program
+            .labels
+            .iter()
+            .map(|(name, adress)| (name.as_str(), *adress))
+            .collect();
+        assert_eq!(
+            labels,
+            [
+                ("data", 0x20E),
+                ("done", 0x20A),
+                ("main", 0x200),
+                ("sub", 0x206)
+            ]
+        );
+    }
+
+    #[test]
+    fn jump_to_main() {
+        // main first needs no jump
+        assert_eq!(rom(": main\n  clear\n"), [0x00, 0xE0]);
+        assert_eq!(rom(":const N 1\n: main\n  v0 := N\n"), [0x60, 0x01]);
+
+        // Otherwise it starts the program
+        assert_eq!(
+            rom(": sub\n  return\n: main\n  sub\n"),
+            [0x12, 0x04, 0x00, 0xEE, 0x22, 0x02]
+        );
+
+        // Adresses taken before main are kept with the jump
+        assert_eq!(rom(": foo : main jump foo\n"), [0x12, 0x02, 0x12, 0x02]);
+        assert_eq!(
+            rom(":calc here { HERE }\n: main\n  i := here\n"),
+            [0x12, 0x02, 0xA2, 0x02]
+        );
+
+        let error = compile_error(": start\n  clear\n");
+        assert_eq!(
+            (error.line, error.message.as_str()),
+            (0, "The program has no main label")
+        );
+    }
+
+    #[test]
+    fn if_then_and_blocks() {
+        assert_eq!(
+            rom(": main\n  if v0 == 5 then v1 := 1\n  if v0 != v2 then v1 := 2\n  if v3 key then v1 := 3\n  if v3 -key then v1 := 4\n"),
+            [0x40, 0x05, 0x61, 0x01, 0x50, 0x20, 0x61, 0x02, 0xE3, 0xA1, 0x61, 0x03, 0xE3, 0x9E, 0x61, 0x04]
+        );
+        assert_eq!(
+            rom(": main\n  if v0 == 1 begin\n    v1 := 1\n  else\n    v1 := 2\n  end\n  clear\n"),
+            [0x30, 0x01, 0x12, 0x08, 0x61, 0x01, 0x12, 0x0A, 0x61, 0x02, 0x00, 0xE0]
+        );
+
+        let blocks = ": main\n  v0 := 1\n  if v0 == 1 begin v1 := 1 else v1 := 2 end\n  if v0 == 2 begin v2 := 1 else v2 := 2 end\n  exit\n";
+        assert_eq!(registers(blocks)[1..3], [1, 2]);
+    }
+
+    #[test]
+    fn loops() {
+        assert_eq!(
+            rom(": main\n  loop\n    v0 += 1\n    while v0 != 10\n    v1 += 1\n  again\n  clear\n"),
+            [0x70, 0x01, 0x40, 0x0A, 0x12, 0x0A, 0x71, 0x01, 0x12, 0x00, 0x00, 0xE0]
+        );
+        let registers = registers(
+            ": main\n  loop\n    v0 += 1\n    while v0 != 10\n    v1 += 2\n    loop\n      v2 += 1\n      while v2 < 3\n    again\n  again\n  exit\n",
+        );
+        // The inner loop counts up to 3 the first time, once per outer iteration after
+        assert_eq!(registers[..3], [10, 18, 11]);
+    }
+
+    #[test]
+    fn comparisons() {
+        assert_eq!(
+            rom(": main\n  if v0 < v1 then clear\n  if v0 > 5 then clear\n"),
+            [
+                0x8F, 0x00, 0x8F, 0x15, 0x4F, 0x00, 0x00, 0xE0, 0x6F, 0x05, 0x8F, 0x05, 0x4F, 0x00,
+                0x00, 0xE0
+            ]
+        );
+
+        // Every operator against a register and a value, as then, begin ... else ... end and while
+        type Comparison = fn(u8, u8) -> bool;
+        let operators: [(&str, Comparison); 6] = [
+            ("==", |a, b| a == b),
+            ("!=", |a, b| a != b),
+            ("<", |a, b| a < b),
+            (">", |a, b| a > b),
+            ("<=", |a, b| a <= b),
+            (">=", |a, b| a >= b),
+        ];
+        for (operator, expected) in operators {
+            for a in [0, 4, 5, 6, 255] {
+                for b in [0, 5, 255] {
+                    let source = format!(
+                        ": main\n  v0 := {a}\n  v1 := {b}\n  if v0 {operator} v1 then v2 := 1\n  if v0 {operator} {b} then v3 := 1\n  if v0 {operator} {b} begin v4 := 1 else v4 := 2 end\n  loop\n    while v0 {operator} {b}\n    v5 := 1\n    exit\n  again\n  exit\n"
+                    );
+                    let registers = registers(&source);
+                    let result = expected(a, b) as u8;
+                    assert_eq!(
+                        registers[2..6],
+                        [result, result, 2 - result, result],
+                        "{} {} {}",
+                        a,
+                        operator,
+                        b
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn aliases_constants_macros_and_calc() {
+        let source = ": main\n  :alias x v3\n  :const N 7\n  :calc M { N * 2 + 1 }\n  :calc P { ( N * 2 ) + 1 }\n  :macro twice reg { reg += N reg += N }\n  x := M\n  x := P\n  twice x\n  :byte { 1 << 4 }\n";
+        assert_eq!(
+            rom(source),
+            [0x63, 21, 0x63, 15, 0x73, 0x07, 0x73, 0x07, 0x10]
+        );
+
+        let error = compile_error(": main\n  :calc x { 1 / 0 }\n");
+        assert_eq!(
+            (error.line, error.message.as_str()),
+            (2, "Division by zero")
+        );
+        let error = compile_error(": main\n  :macro m { m }\n  m\n");
+        assert_eq!(error.message, "Too many macro expansions");
+        let error = compile_error(": main\n  :alias v1 v2\n");
+        assert_eq!(error.message, "v1 can't be used as a name");
+    }
+
+    #[test]
+    fn jumps_past_0xfff() {
+        let cases = [
+            (": main\n  jump far\n:org 0x1000\n: far\n", (2, 8)),
+            (": main\n  jump far\n:org 0x1000\n: far\n  loop\n    v0 += 1\n  again\n", (7, 3)),
+            (": main\n  jump far\n:org 0xFFE\n: far\n  if v0 == 1 begin\n    v0 := 2\n  end\n", (7, 3)),
+            (": main\n  jump far\n:org 0xFFC\n: far\n  loop\n  while v0 != 1\n  v0 += 1\n  again\n", (8, 3)),
+            (":org 0x1000\n: main\n  v0 := 1\n", (2, 3)),
+        ];
+        for (source, position) in cases {
+            let error = compile_error(source);
+            assert_eq!((error.line, error.column), position, "{}", source);
+            assert!(error.message.contains("past 0xFFF"), "{}", error.message);
+        }
+
+        // i := long reaches the whole memory
+        assert_eq!(
+            rom(": main\n  i := long far\n:org 0x1000\n: far\n  0\n")[..4],
+            [0xF0, 0x00, 0x10, 0x00]
+        );
+    }
+
+    #[test]
+    fn errors_point_at_the_line_and_column() {
+        let error = compile_error(": main\n  v0 := 300\n");
+        assert_eq!((error.line, error.column), (2, 9));
+        assert_eq!(
+            error.to_string(),
+            "test.8o:2:9: 300 doesn't fit in 8 bits\n      v0 := 300\n            ^"
+        );
+
+        let error = compile_error(": main\n  jump nowhere\n");
+        assert_eq!((error.line, error.column), (2, 8));
+        assert_eq!(error.message, "Unknown label nowhere");
+
+        let error = compile_error(": main\n  loop\n    v0 += 1\n");
+        assert_eq!((error.line, error.column), (2, 3));
+        assert_eq!(error.message, "This loop has no again");
+
+        let error = compile_error(": main\n  if v0 == 1 clear\n");
+        assert_eq!((error.line, error.column), (2, 14));
+        assert_eq!(error.message, "Expected then or begin, not clear");
+    }
+}
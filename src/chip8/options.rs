@@ -0,0 +1,232 @@
+// Importing useful modules
+use super::audio::{AudioSettings, WavAudio, Waveform};
+use super::font::Font;
+use super::frontend::{AudioSink, NullAudio};
+use super::keymap::Keymap;
+use super::machine::Machine;
+use super::octo::{self, Program};
+use super::quirks::Quirks;
+use super::rewind::{RewindBuffer, DEFAULT_REWIND_BUDGET, DEFAULT_REWIND_SECONDS};
+use super::savestate::SaveSlots;
+use std::fs;
+
+// Command line options shared by all the binaries, describing the machine to run
+pub struct MachineOptions {
+    // Path of the rom to run
+    pub rom_file: Option<String>,
+
+    // Platform specific behaviours (--quirks)
+    pub quirks: Quirks,
+
+    // Font of the hexadecimal digits (--font)
+    pub font: Font,
+
+    // Stack depth overriding the one of the quirks (--stack-depth)
+    pub stack_depth: Option<usize>,
+
+    // Seed of the random numbers generator (--seed)
+    pub seed: Option<u64>,
+
+    // Number of instructions executed per 60 Hz frame (--ipf)
+    pub instructions_per_frame: Option<u32>,
+
+    // How the buzzer sounds (--frequency, --waveform, --volume, --mute)
+    pub audio: AudioSettings,
+
+    // File recording the buzzer instead of playing it (--wav)
+    pub wav_file: Option<String>,
+
+    // Host keys of the keypad (--keymap)
+    pub keymap: Keymap,
+
+    // How far back the rewind goes, 0 to disable it (--rewind)
+    pub rewind_seconds: u32,
+
+    // Memory budget of the rewind in bytes (--rewind-memory, given in KiB)
+    pub rewind_budget: usize,
+}
+
+// Options parsing
+impl MachineOptions {
+    pub fn new() -> MachineOptions {
+        MachineOptions {
+            rom_file: None,
+            quirks: Quirks::vip(),
+            font: Font::Chip8,
+            stack_depth: None,
+            seed: None,
+            instructions_per_frame: None,
+            audio: AudioSettings::default(),
+            wav_file: None,
+            keymap: Keymap::default(),
+            rewind_seconds: DEFAULT_REWIND_SECONDS,
+            rewind_budget: DEFAULT_REWIND_BUDGET,
+        }
+    }
+
+    // Parse one argument, taking its value from args if needed, anything not an option is the rom file
+    pub fn parse(
+        &mut self,
+        arg: String,
+        args: &mut dyn Iterator<Item = String>,
+    ) -> Result<(), String> {
+        let mut value = |name: &str| {
+            args.next()
+                .ok_or_else(|| format!("No value specified after {}!", name))
+        };
+        match arg.as_str() {
+            "--quirks" => {
+                let name = value("--quirks")?;
+                self.quirks =
+                    Quirks::from_name(&name).ok_or(format!("Unknown quirks preset {}!", name))?;
+            }
+            "--stack-depth" => {
+                let depth = value("--stack-depth")?;
+                self.stack_depth = Some(depth.parse().map_err(|_| "Invalid stack depth!")?);
+            }
+            "--seed" => {
+                let seed = value("--seed")?;
+                self.seed = Some(seed.parse().map_err(|_| "Invalid seed!")?);
+            }
+            "--ipf" => {
+                let instructions = value("--ipf")?;
+                self.instructions_per_frame = Some(
+                    instructions
+                        .parse()
+                        .map_err(|_| "Invalid number of instructions per frame!")?,
+                );
+            }
+            "--frequency" => {
+                let frequency = value("--frequency")?;
+                self.audio.frequency = frequency
+                    .parse()
+                    .ok()
+                    .filter(|f: &f32| *f > 0.0)
+                    .ok_or("Invalid frequency!")?;
+            }
+            "--waveform" => {
+                let name = value("--waveform")?;
+                self.audio.waveform =
+                    Waveform::from_name(&name).ok_or(format!("Unknown waveform {}!", name))?;
+            }
+            "--volume" => {
+                let volume = value("--volume")?;
+                self.audio.volume = volume
+                    .parse()
+                    .ok()
+                    .filter(|v| (0.0..=1.0).contains(v))
+                    .ok_or("Invalid volume, it goes from 0 to 1!")?;
+            }
+            "--mute" => self.audio.muted = true,
+            "--wav" => self.wav_file = Some(value("--wav")?),
+            "--keymap" => {
+                let name = value("--keymap")?;
+                self.keymap = match Keymap::from_name(&name) {
+                    Some(keymap) => keymap,
+                    None => Keymap::from_file(&name)?,
+                };
+            }
+            "--rewind" => {
+                let seconds = value("--rewind")?;
+                self.rewind_seconds = seconds.parse().map_err(|_| "Invalid rewind length!")?;
+            }
+            "--rewind-memory" => {
+                let kib: usize = value("--rewind-memory")?
+                    .parse()
+                    .map_err(|_| "Invalid rewind memory budget!")?;
+                self.rewind_budget = kib * 1024;
+            }
+            "--font" => {
+                let name = value("--font")?;
+                self.font =
+                    Font::from_name(&name).map_err(|e| format!("Can't load font: {}", e))?;
+            }
+            _ if arg.starts_with("--") => return Err(format!("Unknown option {}!", arg)),
+            _ => self.rom_file = Some(arg),
+        }
+        Ok(())
+    }
+
+    // Create the machine with the rom loaded
+    pub fn build(&self) -> Result<Machine, String> {
+        self.build_program(&self.program()?)
+    }
+
+    // Read the rom file, Octo sources (.8o) are compiled and keep their labels
+    pub fn program(&self) -> Result<Program, String> {
+        let rom_file = self
+            .rom_file
+            .as_ref()
+            .ok_or("No rom file specified as argument!")?;
+        if rom_file.ends_with(".8o") {
+            return octo::compile_file(rom_file).map_err(|e| format!("Can't compile {}", e));
+        }
+        let rom =
+            fs::read(rom_file).map_err(|e| format!("Can't open rom file {}: {}", rom_file, e))?;
+        Ok(Program {
+            rom,
+            labels: Default::default(),
+        })
+    }
+
+    // Create the machine with a program loaded
+    pub fn build_program(&self, program: &Program) -> Result<Machine, String> {
+        let rom_file = self.rom_file.as_deref().unwrap_or_default();
+        let mut quirks = self.quirks;
+        if let Some(depth) = self.stack_depth {
+            quirks.stack_depth = depth;
+        }
+
+        let mut machine = Machine::new(quirks, self.font.clone())
+            .map_err(|e| format!("Can't create the machine: {}", e))?;
+        if let Some(seed) = self.seed {
+            machine.set_seed(seed);
+        }
+        if let Some(instructions) = self.instructions_per_frame {
+            machine.set_instructions_per_frame(instructions);
+        }
+        machine
+            .load(&program.rom)
+            .map_err(|e| format!("Can't load rom file {}: {}", rom_file, e))?;
+        Ok(machine)
+    }
+}
+
+// Audio output, save states and rewind
+impl MachineOptions {
+    // The rewind buffer, recording one snapshot per frame
+    pub fn rewind_buffer(&self) -> RewindBuffer {
+        RewindBuffer::new(self.rewind_seconds as usize * 60, self.rewind_budget)
+    }
+
+    // Slots of the save states, next to the rom file
+    pub fn save_slots(&self) -> SaveSlots {
+        SaveSlots::new(self.rom_file.as_deref().unwrap_or("rustychip8"))
+    }
+
+    // Where the buzzer goes: the WAV file if any, else the sound device when built with the audio feature
+    pub fn audio_sink(&self) -> Result<Box<dyn AudioSink>, String> {
+        if let Some(wav_file) = &self.wav_file {
+            let wav = WavAudio::create(wav_file, self.audio)
+                .map_err(|e| format!("Can't create {}: {}", wav_file, e))?;
+            return Ok(Box::new(wav));
+        }
+
+        #[cfg(feature = "audio")]
+        if !self.audio.muted {
+            // No sound is better than no emulator
+            match super::speaker::Speaker::new(self.audio) {
+                Ok(speaker) => return Ok(Box::new(speaker)),
+                Err(e) => eprintln!("{}, running without sound", e),
+            }
+        }
+
+        Ok(Box::new(NullAudio))
+    }
+}
+
+impl Default for MachineOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
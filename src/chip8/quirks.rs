@@ -0,0 +1,112 @@
+// How FX55/FX65 leave the I register after storing or loading registers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MemoryIncrement {
+    // I is left untouched
+    None,
+
+    // I is incremented by X
+    X,
+
+    // I is incremented by X + 1, pointing just after the last register
+    XPlusOne,
+}
+
+// Behaviours that differ between the platforms running chip8 programs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Quirks {
+    // 8XY6/8XYE shift Vy into Vx instead of shifting Vx in place
+    pub shift_uses_vy: bool,
+
+    // What FX55/FX65 do to I
+    pub memory_increment: MemoryIncrement,
+
+    // BNNN jumps to XNN + Vx (BXNN) instead of NNN + V0
+    pub jump_uses_vx: bool,
+
+    // 8XY1/8XY2/8XY3 reset VF to 0
+    pub logic_resets_vf: bool,
+
+    // Sprites crossing the screen edges are clipped instead of wrapped
+    pub clip_sprites: bool,
+
+    // DXYN waits for the next 60 Hz frame before executing anything else
+    pub display_wait: bool,
+
+    // Size of the memory in bytes (64 KiB on XO-CHIP)
+    pub memory_size: usize,
+
+    // Maximum depth of nested subroutines calls
+    pub stack_depth: usize,
+}
+
+// Quirks presets
+impl Quirks {
+    // The original COSMAC VIP interpreter
+    pub fn vip() -> Quirks {
+        Quirks {
+            shift_uses_vy: true,
+            memory_increment: MemoryIncrement::XPlusOne,
+            jump_uses_vx: false,
+            logic_resets_vf: true,
+            clip_sprites: true,
+            display_wait: true,
+            memory_size: 4096,
+            stack_depth: 12,
+        }
+    }
+
+    // CHIP-48 on the HP-48 calculators
+    pub fn chip48() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            memory_increment: MemoryIncrement::X,
+            jump_uses_vx: true,
+            logic_resets_vf: false,
+            clip_sprites: true,
+            display_wait: false,
+            memory_size: 4096,
+            stack_depth: 16,
+        }
+    }
+
+    // SUPER-CHIP 1.0
+    pub fn schip10() -> Quirks {
+        Quirks::chip48()
+    }
+
+    // SUPER-CHIP 1.1
+    pub fn schip11() -> Quirks {
+        Quirks {
+            memory_increment: MemoryIncrement::None,
+            ..Quirks::chip48()
+        }
+    }
+
+    // XO-CHIP as implemented by Octo
+    pub fn xochip() -> Quirks {
+        Quirks {
+            shift_uses_vy: true,
+            memory_increment: MemoryIncrement::XPlusOne,
+            jump_uses_vx: false,
+            logic_resets_vf: false,
+            clip_sprites: false,
+            display_wait: false,
+            memory_size: 65536,
+            stack_depth: 16,
+        }
+    }
+
+    // Get a preset by its name
+    pub fn from_name(name: &str) -> Option<Quirks> {
+        match name {
+            "vip" | "chip8" => Some(Quirks::vip()),
+            "chip48" => Some(Quirks::chip48()),
+            "schip10" => Some(Quirks::schip10()),
+            "schip11" | "schip" => Some(Quirks::schip11()),
+            "xochip" => Some(Quirks::xochip()),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,289 @@
+// Importing useful modules
+use super::savestate::CpuState;
+use std::collections::VecDeque;
+
+// Default length of the rewind (seconds)
+pub const DEFAULT_REWIND_SECONDS: u32 = 10;
+
+// Default memory budget of the rewind buffer (bytes)
+pub const DEFAULT_REWIND_BUDGET: usize = 16 * 1024 * 1024;
+
+// Snapshots of the last frames, to play a game backwards.
+// The newest snapshot is kept whole (in the save state binary form), each older one is stored as
+// the difference with the snapshot following it: the XOR of both, run-length encoded, so the
+// memory barely changing from frame to frame costs almost nothing
+pub struct RewindBuffer {
+    // The newest snapshot
+    newest: Option<Vec<u8>>,
+
+    // Differences going back in time, the oldest first
+    deltas: VecDeque<Vec<u8>>,
+
+    // Most snapshots kept, besides the newest
+    max_deltas: usize,
+
+    // Most bytes used by the snapshots, the newest one included
+    budget: usize,
+
+    // Bytes used by the differences
+    used: usize,
+}
+
+// RewindBuffer methods
+impl RewindBuffer {
+    // A buffer holding a number of 60 Hz frames within a memory budget (bytes)
+    pub fn new(frames: usize, budget: usize) -> RewindBuffer {
+        RewindBuffer {
+            newest: None,
+            deltas: VecDeque::new(),
+            max_deltas: frames,
+            budget,
+            used: 0,
+        }
+    }
+
+    // A buffer keeping nothing
+    pub fn disabled() -> RewindBuffer {
+        RewindBuffer::new(0, 0)
+    }
+
+    // If rewinding is possible at all
+    pub fn is_enabled(&self) -> bool {
+        self.max_deltas > 0 && self.budget > 0
+    }
+
+    // Number of frames which can be rewound
+    pub fn len(&self) -> usize {
+        self.deltas.len()
+    }
+
+    // If no frame can be rewound
+    pub fn is_empty(&self) -> bool {
+        self.deltas.is_empty()
+    }
+
+    // Bytes used by the snapshots
+    pub fn memory_used(&self) -> usize {
+        self.used + self.newest.as_ref().map_or(0, |newest| newest.len())
+    }
+
+    // Forget everything, e.g. when the machine is reset or a state is loaded
+    pub fn clear(&mut self) {
+        self.newest = None;
+        self.deltas.clear();
+        self.used = 0;
+    }
+
+    // Record the snapshot of a frame
+    pub fn push(&mut self, state: &CpuState) {
+        if !self.is_enabled() {
+            return;
+        }
+        let snapshot = state.to_bytes();
+        if let Some(newest) = self.newest.take() {
+            let delta = encode_delta(&snapshot, &newest);
+            self.used += delta.len();
+            self.deltas.push_back(delta);
+        }
+        self.newest = Some(snapshot);
+
+        // Dropping the oldest frames past the limits, nothing is kept when the newest snapshot
+        // alone doesn't fit
+        while self.deltas.len() > self.max_deltas || self.memory_used() > self.budget {
+            match self.deltas.pop_front() {
+                Some(delta) => self.used -= delta.len(),
+                None => {
+                    self.newest = None;
+                    break;
+                }
+            }
+        }
+    }
+
+    // Go back one frame, the snapshot before the newest one becomes the newest.
+    // None when there is nothing older
+    pub fn pop(&mut self) -> Option<CpuState> {
+        let delta = self.deltas.pop_back()?;
+        self.used -= delta.len();
+        let newest = self.newest.as_ref()?;
+        let older = decode_delta(newest, &delta);
+        let state = CpuState::from_bytes(&older).ok();
+        self.newest = Some(older);
+        state
+    }
+}
+
+// Difference turning the newer snapshot into the older one: the length of the older one, then
+// runs of unchanged bytes (a count) each followed by changed bytes (a count and the XORed bytes)
+fn encode_delta(newer: &[u8], older: &[u8]) -> Vec<u8> {
+    let len = newer.len().max(older.len());
+    let xor = |i: usize| newer.get(i).copied().unwrap_or(0) ^ older.get(i).copied().unwrap_or(0);
+
+    let mut delta = Vec::new();
+    write_varint(&mut delta, older.len());
+    let mut i = 0;
+    while i < len {
+        let start = i;
+        while i < len && xor(i) == 0 {
+            i += 1;
+        }
+        write_varint(&mut delta, i - start);
+
+        let start = i;
+        while i < len && xor(i) != 0 {
+            i += 1;
+        }
+        write_varint(&mut delta, i - start);
+        delta.extend((start..i).map(xor));
+    }
+    delta
+}
+
+// Rebuild the older snapshot from the newer one and their difference
+fn decode_delta(newer: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut position = 0;
+    let len = read_varint(delta, &mut position);
+    let mut older = newer.to_vec();
+    older.resize(len.max(newer.len()), 0);
+
+    let mut i = 0;
+    while position < delta.len() {
+        i += read_varint(delta, &mut position);
+        let changed = read_varint(delta, &mut position);
+        for byte in &delta[position..position + changed] {
+            older[i] ^= byte;
+            i += 1;
+        }
+        position += changed;
+    }
+    older.truncate(len);
+    older
+}
+
+// Counts take 7 bits per byte, the high bit telling if more bytes follow
+fn write_varint(bytes: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        bytes.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+fn read_varint(bytes: &[u8], position: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = bytes[*position];
+        *position += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::font::Font;
+    use crate::chip8::machine::Machine;
+    use crate::chip8::quirks::Quirks;
+
+    // The states of the first frames of a program drawing random digits and calling a subroutine
+    fn frame_states(frames: usize) -> Vec<CpuState> {
+        let rom = [
+            0xA3, 0x00, 0xC0, 0xFF, 0xF0, 0x33, 0xF0, 0x15, 0xD0, 0x15, 0x71, 0x01, 0x22, 0x10,
+            0x12, 0x02, 0x00, 0xEE,
+        ];
+        let mut machine = Machine::new(Quirks::vip(), Font::Chip8).unwrap();
+        machine.set_seed(21);
+        machine.load(&rom).unwrap();
+        (0..frames)
+            .map(|_| {
+                machine.run_frame().unwrap();
+                machine.state()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn pop_returns_the_previous_states() {
+        let states = frame_states(10);
+        let mut rewind = RewindBuffer::new(60, DEFAULT_REWIND_BUDGET);
+        for state in &states {
+            rewind.push(state);
+        }
+        assert_eq!(rewind.len(), 9);
+        for state in states[..9].iter().rev() {
+            assert_eq!(rewind.pop().as_ref(), Some(state));
+        }
+        assert_eq!(rewind.pop(), None);
+    }
+
+    #[test]
+    fn limits_drop_the_oldest_states() {
+        let states = frame_states(20);
+
+        // Frames limit
+        let mut rewind = RewindBuffer::new(5, DEFAULT_REWIND_BUDGET);
+        for state in &states {
+            rewind.push(state);
+        }
+        assert_eq!(rewind.len(), 5);
+        for state in states[14..19].iter().rev() {
+            assert_eq!(rewind.pop().as_ref(), Some(state));
+        }
+        assert_eq!(rewind.pop(), None);
+
+        // Memory cap, the newest snapshot and half of the differences of all the frames
+        let mut unlimited = RewindBuffer::new(60, DEFAULT_REWIND_BUDGET);
+        for state in &states {
+            unlimited.push(state);
+        }
+        let snapshot = states[19].to_bytes().len();
+        let budget = snapshot + (unlimited.memory_used() - snapshot) / 2;
+        let mut rewind = RewindBuffer::new(60, budget);
+        for state in &states {
+            rewind.push(state);
+            assert!(rewind.memory_used() <= budget);
+        }
+        let kept = rewind.len();
+        assert!(kept > 0 && kept < 19);
+        for state in states[19 - kept..19].iter().rev() {
+            assert_eq!(rewind.pop().as_ref(), Some(state));
+        }
+        assert_eq!(rewind.pop(), None);
+    }
+
+    #[test]
+    fn budget_counts_the_newest_snapshot() {
+        let states = frame_states(3);
+        let snapshot = states[0].to_bytes().len();
+
+        // Room for the newest snapshot only
+        let mut rewind = RewindBuffer::new(60, snapshot);
+        for state in &states {
+            rewind.push(state);
+            assert_eq!(rewind.memory_used(), snapshot);
+        }
+        assert_eq!(rewind.pop(), None);
+
+        // Not even for it
+        let mut rewind = RewindBuffer::new(60, snapshot - 1);
+        for state in &states {
+            rewind.push(state);
+            assert_eq!(rewind.memory_used(), 0);
+        }
+        assert_eq!(rewind.pop(), None);
+    }
+
+    #[test]
+    fn delta_round_trip() {
+        let newer = [1, 2, 3, 4, 5, 6, 7, 8];
+        for older in [&[1, 2, 3, 4, 5, 6, 7, 8][..], &[1, 9, 3], &[0; 300], &[]] {
+            let delta = encode_delta(&newer, older);
+            assert_eq!(decode_delta(&newer, &delta), older);
+        }
+    }
+}
@@ -0,0 +1,37 @@
+// Pseudo random numbers generator of the CPU (xorshift64*), seedable so runs can be reproduced
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rng {
+    state: u64,
+}
+
+// Rng methods
+impl Rng {
+    // Generator starting from a given seed
+    pub fn new(seed: u64) -> Rng {
+        // The state of a xorshift generator must never be 0
+        let state = if seed == 0 {
+            0x9E37_79B9_7F4A_7C15
+        } else {
+            seed
+        };
+        Rng { state }
+    }
+
+    // Generator starting from a random seed
+    pub fn from_entropy() -> Rng {
+        Rng::new(rand::random())
+    }
+
+    // Current state, a generator created from it continues the same sequence
+    pub fn state(&self) -> u64 {
+        self.state
+    }
+
+    // Next random byte
+    pub fn next_u8(&mut self) -> u8 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        (self.state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8
+    }
+}
@@ -0,0 +1,453 @@
+// Importing useful modules
+use super::framebuffer::{Framebuffer, PLANES};
+use super::machine::Machine;
+use super::quirks::{MemoryIncrement, Quirks};
+use std::{error, fmt, fs};
+
+// First bytes of the save state files
+pub const MAGIC: &[u8; 4] = b"RC8S";
+
+// Version of the binary format, bumped on each incompatible change
+pub const VERSION: u16 = 1;
+
+// Why a save state can't be restored
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SaveStateError {
+    // The data doesn't start with the magic bytes
+    BadMagic,
+
+    // The data was written by an incompatible version of the emulator
+    UnsupportedVersion(u16),
+
+    // The data ends too early
+    Truncated,
+
+    // A value makes no sense for the machine (e.g. a stack pointer past the stack)
+    Invalid(&'static str),
+
+    // The JSON form can't be parsed
+    Json(String),
+}
+
+impl fmt::Display for SaveStateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SaveStateError::BadMagic => write!(f, "not a save state"),
+            SaveStateError::UnsupportedVersion(version) => {
+                write!(f, "unsupported save state version {}", version)
+            }
+            SaveStateError::Truncated => write!(f, "truncated save state"),
+            SaveStateError::Invalid(what) => write!(f, "invalid save state: {}", what),
+            SaveStateError::Json(e) => write!(f, "invalid JSON save state: {}", e),
+        }
+    }
+}
+
+impl error::Error for SaveStateError {}
+
+// The complete state of the CPU, everything needed to resume a program exactly where it was
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CpuState {
+    pub pc: u16,
+    pub sp: usize,
+    pub stack: Vec<u16>,
+    pub i_register: u16,
+    pub registers: Vec<u8>,
+    pub ram: Vec<u8>,
+    pub curr_opcode: u16,
+    pub curr_pc: u16,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    pub keys: [bool; 16],
+
+    // FX0A wait: the register receiving the key, and the key pressed since the wait began
+    pub key_wait: Option<(u8, Option<u8>)>,
+    pub vblank_wait: bool,
+    pub exited: bool,
+    pub screen: Framebuffer,
+    pub planes: u8,
+    pub rpl_flags: [u8; 16],
+    pub audio_pattern: Option<[u8; 16]>,
+    pub pitch: u8,
+    pub rng_state: u64,
+    pub quirks: Quirks,
+}
+
+// Binary and JSON forms
+impl CpuState {
+    // The binary form: magic bytes, version, then all the fields in little endian
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = Writer(Vec::with_capacity(self.ram.len() + 1024));
+        w.bytes(MAGIC);
+        w.u16(VERSION);
+
+        // Quirks first, they give the sizes of the memory and the stack
+        let q = &self.quirks;
+        w.bool(q.shift_uses_vy);
+        w.u8(match q.memory_increment {
+            MemoryIncrement::None => 0,
+            MemoryIncrement::X => 1,
+            MemoryIncrement::XPlusOne => 2,
+        });
+        w.bool(q.jump_uses_vx);
+        w.bool(q.logic_resets_vf);
+        w.bool(q.clip_sprites);
+        w.bool(q.display_wait);
+        w.u32(q.memory_size as u32);
+        w.u32(q.stack_depth as u32);
+
+        w.u16(self.pc);
+        w.u32(self.sp as u32);
+        w.u32(self.stack.len() as u32);
+        for adress in &self.stack {
+            w.u16(*adress);
+        }
+        w.u16(self.i_register);
+        w.u32(self.registers.len() as u32);
+        w.bytes(&self.registers);
+        w.u32(self.ram.len() as u32);
+        w.bytes(&self.ram);
+        w.u16(self.curr_opcode);
+        w.u16(self.curr_pc);
+        w.u8(self.delay_timer);
+        w.u8(self.sound_timer);
+        let keys = (0..16).fold(0u16, |mask, k| mask | ((self.keys[k] as u16) << k));
+        w.u16(keys);
+        match self.key_wait {
+            None => w.u8(0),
+            Some((register, None)) => {
+                w.u8(1);
+                w.u8(register);
+            }
+            Some((register, Some(key))) => {
+                w.u8(2);
+                w.u8(register);
+                w.u8(key);
+            }
+        }
+        w.bool(self.vblank_wait);
+        w.bool(self.exited);
+
+        w.u16(self.screen.width() as u16);
+        w.u16(self.screen.height() as u16);
+        for plane in 0..PLANES {
+            for row in self.screen.rows(plane) {
+                w.bytes(&row.to_le_bytes());
+            }
+        }
+        w.u8(self.planes);
+        w.bytes(&self.rpl_flags);
+        match &self.audio_pattern {
+            None => w.u8(0),
+            Some(pattern) => {
+                w.u8(1);
+                w.bytes(pattern);
+            }
+        }
+        w.u8(self.pitch);
+        w.u64(self.rng_state);
+        w.0
+    }
+
+    // Read the binary form
+    pub fn from_bytes(bytes: &[u8]) -> Result<CpuState, SaveStateError> {
+        let mut r = Reader { bytes, position: 0 };
+        if r.bytes(4)? != MAGIC {
+            return Err(SaveStateError::BadMagic);
+        }
+        let version = r.u16()?;
+        if version != VERSION {
+            return Err(SaveStateError::UnsupportedVersion(version));
+        }
+
+        let quirks = Quirks {
+            shift_uses_vy: r.bool()?,
+            memory_increment: match r.u8()? {
+                0 => MemoryIncrement::None,
+                1 => MemoryIncrement::X,
+                2 => MemoryIncrement::XPlusOne,
+                _ => return Err(SaveStateError::Invalid("memory increment quirk")),
+            },
+            jump_uses_vx: r.bool()?,
+            logic_resets_vf: r.bool()?,
+            clip_sprites: r.bool()?,
+            display_wait: r.bool()?,
+            memory_size: r.u32()? as usize,
+            stack_depth: r.u32()? as usize,
+        };
+
+        let pc = r.u16()?;
+        let sp = r.u32()? as usize;
+        let stack_len = r.u32()? as usize;
+        let stack = (0..stack_len).map(|_| r.u16()).collect::<Result<_, _>>()?;
+        let i_register = r.u16()?;
+        let registers_len = r.u32()? as usize;
+        let registers = r.bytes(registers_len)?.to_vec();
+        let ram_len = r.u32()? as usize;
+        let ram = r.bytes(ram_len)?.to_vec();
+        let curr_opcode = r.u16()?;
+        let curr_pc = r.u16()?;
+        let delay_timer = r.u8()?;
+        let sound_timer = r.u8()?;
+        let key_mask = r.u16()?;
+        let keys = std::array::from_fn(|k| key_mask & (1 << k) != 0);
+        let key_wait = match r.u8()? {
+            0 => None,
+            1 => Some((r.u8()?, None)),
+            2 => Some((r.u8()?, Some(r.u8()?))),
+            _ => return Err(SaveStateError::Invalid("key wait")),
+        };
+        let vblank_wait = r.bool()?;
+        let exited = r.bool()?;
+
+        let width = r.u16()? as usize;
+        let height = r.u16()? as usize;
+        if width > 128 {
+            return Err(SaveStateError::Invalid("screen size"));
+        }
+        let mut screen = Framebuffer::new(width, height);
+        for plane in 0..PLANES {
+            for y in 0..height {
+                let row = u128::from_le_bytes(r.bytes(16)?.try_into().unwrap());
+                screen.set_row(plane, y, row);
+            }
+        }
+        let planes = r.u8()?;
+        let rpl_flags = r.bytes(16)?.try_into().unwrap();
+        let audio_pattern = match r.u8()? {
+            0 => None,
+            1 => Some(r.bytes(16)?.try_into().unwrap()),
+            _ => return Err(SaveStateError::Invalid("audio pattern")),
+        };
+        let pitch = r.u8()?;
+        let rng_state = r.u64()?;
+
+        Ok(CpuState {
+            pc,
+            sp,
+            stack,
+            i_register,
+            registers,
+            ram,
+            curr_opcode,
+            curr_pc,
+            delay_timer,
+            sound_timer,
+            keys,
+            key_wait,
+            vblank_wait,
+            exited,
+            screen,
+            planes,
+            rpl_flags,
+            audio_pattern,
+            pitch,
+            rng_state,
+            quirks,
+        })
+    }
+
+    // The JSON form, for inspection
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("A CPU state is always serializable")
+    }
+
+    // Read the JSON form
+    #[cfg(feature = "serde")]
+    pub fn from_json(json: &str) -> Result<CpuState, SaveStateError> {
+        serde_json::from_str(json).map_err(|e| SaveStateError::Json(e.to_string()))
+    }
+}
+
+// Save state files, in the JSON form when their name ends with .json (with the serde feature)
+impl CpuState {
+    // Write the state to a file
+    pub fn save_file(&self, path: &str) -> Result<(), String> {
+        let bytes = if path.ends_with(".json") {
+            #[cfg(feature = "serde")]
+            {
+                self.to_json().into_bytes()
+            }
+            #[cfg(not(feature = "serde"))]
+            return Err("JSON save states need the serde feature".to_string());
+        } else {
+            self.to_bytes()
+        };
+        fs::write(path, bytes).map_err(|e| format!("Can't write {}: {}", path, e))
+    }
+
+    // Read a state from a file, in either form
+    pub fn load_file(path: &str) -> Result<CpuState, String> {
+        let bytes = fs::read(path).map_err(|e| format!("Can't read {}: {}", path, e))?;
+        #[cfg(feature = "serde")]
+        if !bytes.starts_with(MAGIC) {
+            let json = String::from_utf8_lossy(&bytes);
+            return CpuState::from_json(&json).map_err(|e| format!("Can't load {}: {}", path, e));
+        }
+        CpuState::from_bytes(&bytes).map_err(|e| format!("Can't load {}: {}", path, e))
+    }
+}
+
+// Save states files of a rom, in numbered slots next to it
+pub struct SaveSlots {
+    rom_file: String,
+}
+
+// SaveSlots methods
+impl SaveSlots {
+    pub fn new(rom_file: &str) -> SaveSlots {
+        SaveSlots {
+            rom_file: rom_file.to_string(),
+        }
+    }
+
+    // File of a slot
+    pub fn path(&self, slot: u8) -> String {
+        format!("{}.{}.state", self.rom_file, slot)
+    }
+
+    // Save the machine in a slot
+    pub fn save(&self, machine: &Machine, slot: u8) -> Result<(), String> {
+        machine.state().save_file(&self.path(slot))
+    }
+
+    // Restore the machine from a slot
+    pub fn load(&self, machine: &mut Machine, slot: u8) -> Result<(), String> {
+        let path = self.path(slot);
+        let state = CpuState::load_file(&path)?;
+        machine
+            .load_state(state)
+            .map_err(|e| format!("Can't load {}: {}", path, e))
+    }
+}
+
+// Appends little endian values to a buffer
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.0.extend_from_slice(bytes);
+    }
+
+    fn u8(&mut self, value: u8) {
+        self.0.push(value);
+    }
+
+    fn bool(&mut self, value: bool) {
+        self.0.push(value as u8);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.bytes(&value.to_le_bytes());
+    }
+}
+
+// Reads little endian values from a buffer
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], SaveStateError> {
+        let end = self
+            .position
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or(SaveStateError::Truncated)?;
+        let bytes = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, SaveStateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn bool(&mut self) -> Result<bool, SaveStateError> {
+        Ok(self.u8()? != 0)
+    }
+
+    fn u16(&mut self) -> Result<u16, SaveStateError> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, SaveStateError> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, SaveStateError> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::font::Font;
+
+    // A machine a few frames into a program drawing random digits and calling a subroutine
+    fn running_machine() -> Machine {
+        let rom = [
+            0xA3, 0x00, 0xC0, 0xFF, 0xF0, 0x33, 0xF0, 0x15, 0xD0, 0x15, 0x71, 0x01, 0x22, 0x10,
+            0x12, 0x02, 0x00, 0xEE,
+        ];
+        let mut machine = Machine::new(Quirks::vip(), Font::Chip8).unwrap();
+        machine.set_seed(8);
+        machine.load(&rom).unwrap();
+        for _ in 0..3 {
+            machine.run_frame().unwrap();
+        }
+        machine
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let state = running_machine().state();
+        let bytes = state.to_bytes();
+        assert!(bytes.starts_with(MAGIC));
+        assert_eq!(CpuState::from_bytes(&bytes), Ok(state));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_round_trip() {
+        let state = running_machine().state();
+        assert_eq!(CpuState::from_json(&state.to_json()), Ok(state));
+    }
+
+    #[test]
+    fn truncated_state_is_rejected() {
+        let bytes = running_machine().state().to_bytes();
+        for len in 0..bytes.len() {
+            assert_eq!(
+                CpuState::from_bytes(&bytes[..len]),
+                Err(SaveStateError::Truncated)
+            );
+        }
+    }
+
+    #[test]
+    fn bad_magic_and_version_are_rejected() {
+        let mut bytes = running_machine().state().to_bytes();
+        bytes[4] = 0xFF;
+        assert_eq!(
+            CpuState::from_bytes(&bytes),
+            Err(SaveStateError::UnsupportedVersion(u16::from_le_bytes([
+                0xFF, 0
+            ])))
+        );
+        bytes[0] = b'X';
+        assert_eq!(CpuState::from_bytes(&bytes), Err(SaveStateError::BadMagic));
+    }
+}
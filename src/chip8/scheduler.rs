@@ -0,0 +1,60 @@
+// Importing useful modules
+use std::time::{Duration, Instant};
+
+// Rate of the emulated frames (Hz), the rate of the timers
+pub const FRAME_RATE: u32 = 60;
+
+// Most frames run at once to catch up after a slow frame, beyond that the emulation slows down
+const MAX_CATCH_UP: u32 = 4;
+
+// Paces the emulated frames on the wall clock, however often the frontend hands control back
+pub struct Scheduler {
+    // Duration of an emulated frame
+    frame_duration: Duration,
+
+    // When the next frame is due
+    next_frame: Instant,
+}
+
+// Scheduler methods
+impl Scheduler {
+    // A scheduler whose first frame is due now
+    pub fn new(frame_rate: u32) -> Scheduler {
+        Scheduler {
+            frame_duration: Duration::from_secs(1) / frame_rate,
+            next_frame: Instant::now(),
+        }
+    }
+
+    // Number of frames to run now, 0 when it is too early for the next one
+    pub fn frames_due(&mut self) -> u32 {
+        let now = Instant::now();
+        let mut frames = 0;
+        while self.next_frame <= now && frames < MAX_CATCH_UP {
+            self.next_frame += self.frame_duration;
+            frames += 1;
+        }
+
+        // Too far behind (the window was dragged, the machine was suspended...), the frames are dropped
+        if self.next_frame <= now {
+            self.next_frame = now + self.frame_duration;
+        }
+        frames
+    }
+
+    // Start over after a pause, the next frame is due now and the paused time isn't caught up
+    pub fn restart(&mut self) {
+        self.next_frame = Instant::now();
+    }
+
+    // Time left before the next frame is due
+    pub fn until_next_frame(&self) -> Duration {
+        self.next_frame.saturating_duration_since(Instant::now())
+    }
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self::new(FRAME_RATE)
+    }
+}
@@ -0,0 +1,88 @@
+// Importing useful modules
+extern crate cpal;
+
+use super::audio::{AudioSettings, Tone};
+use super::frontend::AudioSink;
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{FromSample, SampleFormat, SizedSample, Stream, StreamConfig};
+use std::sync::{Arc, Mutex};
+
+// Audio sink playing the buzzer on the default sound device
+pub struct Speaker {
+    // The tone, shared with the audio thread filling the device buffers
+    tone: Arc<Mutex<Tone>>,
+
+    // The sound plays as long as the stream lives
+    _stream: Stream,
+}
+
+// Speaker methods
+impl Speaker {
+    // Open the default output device
+    pub fn new(settings: AudioSettings) -> Result<Speaker, String> {
+        let device = cpal::default_host()
+            .default_output_device()
+            .ok_or("No sound device available")?;
+        let supported = device
+            .default_output_config()
+            .map_err(|e| format!("Can't configure the sound device: {}", e))?;
+
+        let tone = Arc::new(Mutex::new(Tone::new(settings)));
+        let config = supported.config();
+        let stream = match supported.sample_format() {
+            SampleFormat::F32 => build_stream::<f32>(&device, &config, tone.clone()),
+            SampleFormat::I16 => build_stream::<i16>(&device, &config, tone.clone()),
+            SampleFormat::U16 => build_stream::<u16>(&device, &config, tone.clone()),
+            format => return Err(format!("Unsupported sample format {}", format)),
+        }?;
+        stream
+            .play()
+            .map_err(|e| format!("Can't start the sound: {}", e))?;
+
+        Ok(Speaker {
+            tone,
+            _stream: stream,
+        })
+    }
+}
+
+// Create a stream filling each channel with the tone
+fn build_stream<T>(
+    device: &cpal::Device,
+    config: &StreamConfig,
+    tone: Arc<Mutex<Tone>>,
+) -> Result<Stream, String>
+where
+    T: SizedSample + FromSample<f32>,
+{
+    let sample_rate = config.sample_rate.0;
+    let channels = config.channels as usize;
+    device
+        .build_output_stream(
+            config,
+            move |data: &mut [T], _| {
+                let mut tone = tone.lock().unwrap();
+                for frame in data.chunks_mut(channels) {
+                    let sample = T::from_sample(tone.sample(sample_rate));
+                    frame.fill(sample);
+                }
+            },
+            |e| eprintln!("Sound error: {}", e),
+            None,
+        )
+        .map_err(|e| format!("Can't open the sound stream: {}", e))
+}
+
+impl AudioSink for Speaker {
+    fn set_buzzer(&mut self, active: bool) {
+        self.tone.lock().unwrap().set_active(active);
+    }
+
+    fn set_pattern(&mut self, pattern: Option<&[u8; 16]>, pitch: u8) {
+        self.tone.lock().unwrap().set_pattern(pattern, pitch);
+    }
+
+    fn pause(&mut self) {
+        self.tone.lock().unwrap().set_active(false);
+    }
+}
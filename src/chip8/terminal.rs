@@ -0,0 +1,276 @@
+// Importing useful modules
+extern crate crossterm;
+
+use super::framebuffer::Framebuffer;
+use super::frontend::{DisplaySink, InputEvent, InputSource};
+use super::keymap::Keymap;
+use crossterm::event::{
+    self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
+    PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+};
+use crossterm::style::{Color, Print, SetBackgroundColor, SetForegroundColor};
+use crossterm::{cursor, execute, queue, terminal};
+use std::io::{self, Stdout, Write};
+use std::time::{Duration, Instant};
+
+// Most terminals only report key presses, a key is released when it stops repeating for this long.
+// It must be longer than the delay before the auto-repeat starts (250 to 600 ms usually), or a
+// held key would be released then pressed again, which breaks FX0A waits and movements
+const KEY_RELEASE_DELAY: Duration = Duration::from_millis(700);
+
+// Colors of the pixels by the XO-CHIP planes they are lit on: none, first, second, both
+const PALETTE: [Color; 4] = [
+    Color::Rgb { r: 0, g: 0, b: 0 },
+    Color::Rgb {
+        r: 255,
+        g: 255,
+        b: 255,
+    },
+    Color::Rgb {
+        r: 255,
+        g: 102,
+        b: 0,
+    },
+    Color::Rgb {
+        r: 102,
+        g: 33,
+        b: 0,
+    },
+];
+
+// A frontend drawing the screen in the terminal with half blocks, two pixels per character,
+// and reading the keyboard of the terminal
+pub struct Terminal {
+    stdout: Stdout,
+
+    // The screen last drawn, nothing is redrawn while it does not change
+    screen: Framebuffer,
+
+    // When each keypad key was last seen pressed
+    pressed: [Option<Instant>; 16],
+
+    // When the rewind hotkey was last seen pressed
+    rewind_pressed: Option<Instant>,
+
+    // If the terminal reports key releases, otherwise they are guessed
+    reports_releases: bool,
+
+    // Host keys of the keypad
+    keymap: Keymap,
+}
+
+// Terminal methods
+impl Terminal {
+    // Switch the terminal to raw mode on the alternate screen
+    pub fn new(keymap: Keymap) -> io::Result<Terminal> {
+        let mut stdout = io::stdout();
+        terminal::enable_raw_mode()?;
+        execute!(
+            stdout,
+            terminal::EnterAlternateScreen,
+            cursor::Hide,
+            terminal::Clear(terminal::ClearType::All)
+        )?;
+
+        // Asking for key releases to terminals supporting it (kitty keyboard protocol)
+        let reports_releases = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if reports_releases {
+            execute!(
+                stdout,
+                PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES)
+            )?;
+        }
+
+        Ok(Terminal {
+            stdout,
+            screen: Framebuffer::new(0, 0),
+            pressed: [None; 16],
+            rewind_pressed: None,
+            reports_releases,
+            keymap,
+        })
+    }
+
+    // Draw the screen buffer, each character is a pixel over another one
+    fn draw(&mut self) -> io::Result<()> {
+        let width = self.screen.width();
+        let height = self.screen.height();
+        for row in (0..height).step_by(2) {
+            queue!(self.stdout, cursor::MoveTo(0, (row / 2) as u16))?;
+            for x in 0..width {
+                let top = PALETTE[self.screen.pixel(x, row) as usize];
+                let bottom = if row + 1 < height {
+                    PALETTE[self.screen.pixel(x, row + 1) as usize]
+                } else {
+                    PALETTE[0]
+                };
+                queue!(
+                    self.stdout,
+                    SetForegroundColor(top),
+                    SetBackgroundColor(bottom),
+                    Print('▀')
+                )?;
+            }
+        }
+        queue!(
+            self.stdout,
+            crossterm::style::ResetColor,
+            cursor::MoveTo(0, height.div_ceil(2) as u16),
+            Print(format!(
+                "{}x{}  Esc: quit  F1-F9: load state  Shift+F1-F9: save state  Backspace: rewind",
+                width, height
+            ))
+        )?;
+        self.stdout.flush()
+    }
+
+    // Handle a key of the terminal, returns false if the user wants to quit
+    fn key(&mut self, key: KeyEvent, input: &mut Vec<InputEvent>) -> bool {
+        let keypad = match key.code {
+            KeyCode::Esc => return false,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return false,
+            // Rewind hotkey, held to play the game backwards
+            KeyCode::Backspace => {
+                if key.kind == KeyEventKind::Release {
+                    self.rewind_pressed = None;
+                    input.push(InputEvent::Rewind(false));
+                } else {
+                    if self.rewind_pressed.is_none() {
+                        input.push(InputEvent::Rewind(true));
+                    }
+                    self.rewind_pressed = Some(Instant::now());
+                }
+                return true;
+            }
+            // Save states hotkeys: F1-F9 load a slot, with shift they save it
+            KeyCode::F(slot @ 1..=9) => {
+                if key.kind == KeyEventKind::Press {
+                    input.push(if key.modifiers.contains(KeyModifiers::SHIFT) {
+                        InputEvent::SaveState(slot)
+                    } else {
+                        InputEvent::LoadState(slot)
+                    });
+                }
+                return true;
+            }
+            KeyCode::Char(c) => self.keymap.key_for_char(c),
+            KeyCode::Enter => self.keymap.key("enter"),
+            KeyCode::Tab => self.keymap.key("tab"),
+            KeyCode::Up => self.keymap.key("up"),
+            KeyCode::Down => self.keymap.key("down"),
+            KeyCode::Left => self.keymap.key("left"),
+            KeyCode::Right => self.keymap.key("right"),
+            _ => None,
+        };
+
+        if let Some(k) = keypad {
+            let index = k as usize;
+            if key.kind == KeyEventKind::Release {
+                self.pressed[index] = None;
+                input.push(InputEvent::Key {
+                    key: k,
+                    pressed: false,
+                });
+            } else {
+                if self.pressed[index].is_none() {
+                    input.push(InputEvent::Key {
+                        key: k,
+                        pressed: true,
+                    });
+                }
+                self.pressed[index] = Some(Instant::now());
+            }
+        }
+        true
+    }
+}
+
+// Leaving the terminal as it was found
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        if self.reports_releases {
+            let _ = execute!(self.stdout, PopKeyboardEnhancementFlags);
+        }
+        let _ = execute!(
+            self.stdout,
+            crossterm::style::ResetColor,
+            cursor::Show,
+            terminal::LeaveAlternateScreen
+        );
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+// The terminal is redrawn when the screen changes
+impl DisplaySink for Terminal {
+    fn present(&mut self, screen: &Framebuffer) {
+        if self.screen != *screen {
+            // The terminal may leave garbage behind when the resolution changes
+            if self.screen.width() != screen.width() {
+                let _ = queue!(self.stdout, terminal::Clear(terminal::ClearType::All));
+            }
+            self.screen.clone_from(screen);
+            let _ = self.draw();
+        }
+    }
+}
+
+// Reading the terminal events until the timeout elapses
+impl InputSource for Terminal {
+    fn poll(&mut self, timeout: Duration) -> Vec<InputEvent> {
+        let mut input = Vec::new();
+        let deadline = Instant::now() + timeout;
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            match event::poll(deadline - now) {
+                Ok(true) => match event::read() {
+                    Ok(Event::Key(key)) => {
+                        if !self.key(key, &mut input) {
+                            input.push(InputEvent::Quit);
+                            return input;
+                        }
+                    }
+                    Ok(Event::Resize(_, _)) => {
+                        let _ = queue!(self.stdout, terminal::Clear(terminal::ClearType::All));
+                        let _ = self.draw();
+                    }
+                    Ok(_) => {}
+                    Err(_) => {
+                        input.push(InputEvent::Quit);
+                        return input;
+                    }
+                },
+                Ok(false) => {}
+                Err(_) => {
+                    input.push(InputEvent::Quit);
+                    return input;
+                }
+            }
+        }
+
+        // Releasing the keys which stopped repeating
+        let now = Instant::now();
+        for (key, pressed) in self.pressed.iter_mut().enumerate() {
+            if !self.reports_releases && pressed.is_some_and(|time| now - time >= KEY_RELEASE_DELAY)
+            {
+                *pressed = None;
+                input.push(InputEvent::Key {
+                    key: key as u8,
+                    pressed: false,
+                });
+            }
+        }
+        if !self.reports_releases
+            && self
+                .rewind_pressed
+                .is_some_and(|time| now - time >= KEY_RELEASE_DELAY)
+        {
+            self.rewind_pressed = None;
+            input.push(InputEvent::Rewind(false));
+        }
+        input
+    }
+}
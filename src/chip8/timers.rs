@@ -0,0 +1,22 @@
+// The delay and sound timers of the chip8, both counting down at 60 Hz
+#[derive(Default)]
+pub struct Timers {
+    // Delay timer (DT)
+    pub delay: u8,
+
+    // Sound timer (ST), the buzzer sounds while it is non-zero
+    pub sound: u8,
+}
+
+// Timers methods
+impl Timers {
+    pub fn new() -> Timers {
+        Timers { delay: 0, sound: 0 }
+    }
+
+    // Decrement both timers once, this is one 60 Hz tick
+    pub fn tick(&mut self) {
+        self.delay = self.delay.saturating_sub(1);
+        self.sound = self.sound.saturating_sub(1);
+    }
+}
@@ -0,0 +1,4 @@
+// Importing all useful modules
+pub mod chip8;
+
+pub use chip8::machine::Machine;
@@ -1,51 +1,58 @@
 // Importing all useful modules
-mod chip8;
-use chip8::cpu::Cpu;
-use chip8::gpu::Gpu;
-
-use piston::event_loop::{EventSettings, Events};
-use piston::input::{RenderEvent, UpdateEvent};
+use rustychip_8::chip8::debugger::Debugger;
+use rustychip_8::chip8::frontend;
+use rustychip_8::chip8::gpu::Gpu;
+use rustychip_8::chip8::options::MachineOptions;
 
 use std::env;
 
 // Pixel Size
 const SIZE_FACTOR: u32 = 4;
 
-// If outputing log to console
-const DEBUG: bool = false;
-
 // Main entry point
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        panic!("No rom file specified as argument!");
-    }
-
-    // The instance of the CPU
-    let mut cpu = Cpu::new(Some(args[1].clone()));
-
-    // The instance of the GPU
-    let mut gpu = Gpu::new(SIZE_FACTOR);
-
-    // Handling events
-    let mut events = Events::new(EventSettings::new());
-    while let Some(e) = events.next(&mut gpu.window) {
-        // Render graphics
-        if let Some(args) = e.render_args() {
-            gpu.render(&args);
+    // Parsing command line arguments
+    let mut options = MachineOptions::new();
+    let mut debug = false;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--debug" => debug = true,
+            _ => options
+                .parse(arg, &mut args)
+                .unwrap_or_else(|e| panic!("{}", e)),
         }
+    }
 
-        // Update graphics logic
-        if let Some(_args) = e.update_args() {
-            gpu.update(cpu.get_scree_buffer());
-        }
+    // The instance of the machine, Octo sources are compiled first
+    let program = options.program().unwrap_or_else(|e| panic!("{}", e));
+    let mut machine = options
+        .build_program(&program)
+        .unwrap_or_else(|e| panic!("{}", e));
 
-        // CPU step
-        cpu.run();
+    // Where the buzzer sounds
+    let mut audio = options.audio_sink().unwrap_or_else(|e| panic!("{}", e));
 
-        // For debugging
-        if DEBUG == true {
-            // cpu.debuggerStep();
-        }
+    // The instance of the GPU
+    let mut gpu = Gpu::new(SIZE_FACTOR, options.keymap.clone());
+
+    // The debugger prompt reads commands on the console, the machine starts paused
+    let mut debugger = debug.then(|| {
+        let mut debugger = Debugger::new();
+        debugger.set_labels(program.labels);
+        debugger
+    });
+
+    // Running the emulation, it stops on the first error or when the program exits
+    if let Err(error) = frontend::run(
+        &mut machine,
+        &mut gpu,
+        audio.as_mut(),
+        &options.save_slots(),
+        &mut options.rewind_buffer(),
+        debugger.as_mut(),
+    ) {
+        eprintln!("Emulation stopped: {}", error);
+        std::process::exit(1);
     }
 }